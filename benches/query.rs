@@ -1,23 +1,19 @@
 use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
-use firn::{db::*, extractor::*, handle::Handle, make_extractors, query, run_extractors};
-use std::{env::current_dir, error::Error};
+use firn::{
+    config::{Config, Overrides},
+    db::*,
+    handle::Handle,
+    query, run_extractors,
+};
+use std::error::Error;
 
 fn build_db() -> Result<Handle, Box<dyn Error>> {
-    let storage = current_dir()?.join("data/storage");
-    std::fs::create_dir_all(&storage)?;
+    // Criterion's own arguments are not meant for us, so only the file and environment apply
+    let config = Config::load_with(&Overrides::from_env(|name| std::env::var(name).ok()))?;
 
     let (database, write_log) = Database::new();
-    let mut handle = Handle::new(database, storage.clone());
-
-    let mut extractors = make_extractors![
-        BlobLoader(storage.clone()),
-        MimeInfer,
-        ExifExtractor,
-        GeoNames::load(
-            "/Users/tibl/Downloads/DE/DE.txt",
-            "/Users/tibl/Downloads/hierarchy.txt"
-        )?
-    ];
+    let mut handle = config.handle(database)?;
+    let mut extractors = config.extractors()?;
 
     run_extractors(write_log, &mut handle, &mut extractors)?;
 
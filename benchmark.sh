@@ -7,7 +7,7 @@ sudo hyperfine --prepare "sync && purge" --min-runs 30 --export-json results.pur
     ./target/release/enumerate_tree \
     ./target/release/query_exif_flat \
     ./target/release/query_exif_tree \
-    ./target/release/build_db
+    "./target/release/firn extract"
 
 hyperfine --warmup 30 --min-runs 30 --export-json results.cache.json \
     ./target/release/lookup_single \
@@ -16,7 +16,7 @@ hyperfine --warmup 30 --min-runs 30 --export-json results.cache.json \
     ./target/release/enumerate_tree \
     ./target/release/query_exif_flat \
     ./target/release/query_exif_tree \
-    ./target/release/build_db
+    "./target/release/firn extract"
 
 cargo bench --bench query -- --warm-up-time 10 --measurement-time 60
 
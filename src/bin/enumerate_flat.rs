@@ -1,7 +1,8 @@
-use std::{env::current_dir, error::Error};
+use firn::config::Config;
+use std::error::Error;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let storage = current_dir()?.join("data/storage");
+    let storage = Config::load()?.storage;
 
     let mut total_size = 0;
     let mut total_name = 0;
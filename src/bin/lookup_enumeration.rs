@@ -1,12 +1,12 @@
+use firn::config::Config;
 use std::error::Error;
-
 use walkdir::WalkDir;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let mut total_size = 0;
     let mut total_name = 0;
 
-    for entry in WalkDir::new("data/hierarchy/time/2021") {
+    for entry in WalkDir::new(Config::load()?.hierarchy.join("time/2021")) {
         let file = entry?;
         if file.file_type().is_file() {
             if let Ok(name) = file.file_name().to_owned().into_string() {
@@ -1,8 +1,10 @@
-use std::{error::Error, path::PathBuf};
+use firn::config::Config;
+use std::error::Error;
 
 fn main() -> Result<(), Box<dyn Error>> {
-    let path: PathBuf =
-        "data/hierarchy/time/2018/April/16/9A8A9515-4D61-4890-8B88-E251D2E94B52.heic".into();
+    let path = Config::load()?
+        .hierarchy
+        .join("time/2018/April/16/9A8A9515-4D61-4890-8B88-E251D2E94B52.heic");
 
     let metadata = std::fs::metadata(&path)?;
 
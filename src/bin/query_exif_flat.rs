@@ -1,10 +1,10 @@
-use firn::extractor::ExifExtractor;
+use firn::{config::Config, extractor::ExifExtractor};
 use std::{error::Error, fs::File, io::BufReader};
 
 fn main() -> Result<(), Box<dyn Error>> {
     let mut total_pixels = 0u128;
 
-    for entry in std::fs::read_dir("data/storage")? {
+    for entry in std::fs::read_dir(Config::load()?.storage)? {
         let file = entry?;
 
         if file.file_type()?.is_file() {
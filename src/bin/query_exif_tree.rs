@@ -1,11 +1,11 @@
-use firn::extractor::ExifExtractor;
+use firn::{config::Config, extractor::ExifExtractor};
 use std::{error::Error, fs::File, io::BufReader};
 use walkdir::WalkDir;
 
 fn main() -> Result<(), Box<dyn Error>> {
     let mut total_pixels = 0u128;
 
-    for entry in WalkDir::new("data/hierarchy") {
+    for entry in WalkDir::new(Config::load()?.hierarchy) {
         let file = entry?;
 
         if file.file_type().is_file() {
@@ -0,0 +1,109 @@
+//! Consistency checks of the storage and the extracted facts
+
+use super::{FsckReport, Problem, ProblemKind};
+use crate::{
+    db::{Entity, Value},
+    extractor::FailureReport,
+    handle::{Handle, DERIVED_DIR},
+};
+use std::{collections::HashSet, error::Error, path::PathBuf};
+use walkdir::WalkDir;
+
+pub fn check(handle: &Handle) -> Result<FsckReport, Box<dyn Error>> {
+    let mut report = FsckReport::default();
+    let mut problem = |kind, subject: &dyn ToString, message: String| {
+        report.problems.push(Problem {
+            kind,
+            subject: subject.to_string(),
+            message,
+        })
+    };
+
+    for root in handle.roots() {
+        if !root.path.is_dir() {
+            problem(
+                ProblemKind::UnavailableRoot,
+                &root.name,
+                format!("{} is not a directory", root.path.display()),
+            );
+        }
+    }
+
+    let mut entities = HashSet::new();
+    let mut references = HashSet::new();
+    let mut blobs = HashSet::new();
+
+    for (entity, attribute, values) in handle.eav.scan() {
+        entities.insert(entity);
+
+        for value in values {
+            if let Value::Reference(target) = value {
+                references.insert((entity, target));
+            }
+        }
+
+        if attribute.0 != "blob/path" {
+            continue;
+        }
+
+        match handle.blob_path(entity) {
+            Ok(path) if path.is_file() => {
+                blobs.insert(path);
+            }
+            Ok(path) => problem(
+                ProblemKind::MissingBlob,
+                &entity.0,
+                format!("{} does not exist", path.display()),
+            ),
+            Err(e) => problem(ProblemKind::MissingBlob, &entity.0, e.to_string()),
+        }
+    }
+
+    let mut dangling: Vec<(&Entity, &Entity)> = references
+        .into_iter()
+        .filter(|(_, target)| !entities.contains(target))
+        .collect();
+    dangling.sort_by(|a, b| (&a.0 .0, &a.1 .0).cmp(&(&b.0 .0, &b.1 .0)));
+
+    for (source, target) in dangling {
+        problem(
+            ProblemKind::DanglingReference,
+            &source.0,
+            format!("references {} which has no facts", target.0),
+        );
+    }
+
+    let derived = handle.primary_root().path.join(DERIVED_DIR);
+    if derived.is_dir() {
+        for entry in WalkDir::new(&derived).sort_by_file_name() {
+            let entry = entry?;
+            let path: PathBuf = entry.path().to_owned();
+
+            if entry.file_type().is_file() && !blobs.contains(&path) {
+                problem(
+                    ProblemKind::OrphanedDerivedBlob,
+                    &path.display(),
+                    "is not referenced by any blob".to_owned(),
+                );
+            }
+        }
+    }
+
+    for failure in FailureReport::load(handle).failures {
+        let subject = match &failure.entity {
+            Some(entity) => entity.0.clone(),
+            None => failure.extractor.clone(),
+        };
+
+        problem(
+            ProblemKind::ExtractorFailure,
+            &subject,
+            format!(
+                "{} failed ({}): {}",
+                failure.extractor, failure.kind, failure.message
+            ),
+        );
+    }
+
+    Ok(report)
+}
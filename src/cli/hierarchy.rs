@@ -0,0 +1,62 @@
+//! Directories of images sorted by camera and by date
+
+use super::HierarchyReport;
+use crate::{db::*, handle::Handle, query};
+use std::{
+    error::Error,
+    fs::{copy, create_dir_all, File},
+    path::{Path, PathBuf},
+};
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+/// Rebuilds the hierarchy below `root`, with `empty` only creating empty files which is a lot
+/// faster for trying things out
+pub fn build_hierarchy(
+    handle: &mut Handle,
+    root: impl Into<PathBuf>,
+    empty: bool,
+) -> Result<HierarchyReport, Box<dyn Error>> {
+    let root = root.into();
+    std::fs::remove_dir_all(&root).ok();
+
+    query!(handle where (?time, ?make, #model, #image) match [
+        { #model, :"device/manufacturer", ?make },
+        { #image, :"image/camera", #model },
+        { #image, :"time/creation", ?time}
+    ] => images);
+
+    for entry in images.iter() {
+        let make = entry.get(&make).unwrap().data();
+        let time = entry.get(&time).unwrap().data();
+        let model = &entry.get(&model).unwrap().0;
+        let image_id = &entry.get(&image).unwrap().0;
+
+        let datetime = OffsetDateTime::parse(time, &Rfc3339)?;
+        let year = datetime.year();
+        let month = datetime.month();
+        let day = datetime.day();
+
+        let path_cam = root.join("cam").join(format!("{make}/{model}"));
+        let path_time = root.join("time").join(format!("{year}/{month}/{day}"));
+
+        create_dir_all(&path_cam)?;
+        create_dir_all(&path_time)?;
+
+        // Blobs in nested directories are identified by their relative path
+        let name = Path::new(image_id).file_name().unwrap();
+
+        if empty {
+            File::create(path_cam.join(name))?;
+            File::create(path_time.join(name))?;
+        } else {
+            let src = handle.blob_path(entry.get(&image).unwrap())?;
+            copy(&src, path_cam.join(name))?;
+            copy(&src, path_time.join(name))?;
+        }
+    }
+
+    Ok(HierarchyReport {
+        root,
+        images: images.len(),
+    })
+}
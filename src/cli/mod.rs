@@ -0,0 +1,622 @@
+//! The `firn` command line interface. Every command loads the [`Config`], builds the database by
+//! running the configured extractors over the storage and then works with the result.
+
+use crate::{
+    config::{Config, Overrides},
+    db::{Attribute, Database, Entity, Fact, Query, Value},
+    drain_write_log,
+    extractor::{BlobLoader, Extractor, FailureReport, RetryPolicy},
+    handle::{Handle, ImportMode},
+    init_extractors,
+};
+use clap::{Parser, Subcommand, ValueEnum};
+use serde::Serialize;
+use std::{
+    cell::Cell,
+    error::Error,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    rc::Rc,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+use walkdir::WalkDir;
+
+mod fsck;
+mod hierarchy;
+mod repl;
+mod report;
+mod serve;
+
+pub use report::*;
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "firn",
+    version,
+    about = "Extracts metadata from files into a queryable graph"
+)]
+pub struct Cli {
+    #[command(flatten)]
+    pub overrides: Overrides,
+
+    /// How results are printed
+    #[arg(long, global = true, value_enum, default_value_t = Format::Human)]
+    pub format: Format,
+
+    /// Start from the facts dumped to the database file, so extractors only process blobs
+    /// that changed or failed since
+    #[arg(long, global = true)]
+    pub restore: bool,
+
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Copies files or whole directories into the primary storage root. The names of the files
+    /// are added to the database file, as they cannot be extracted again.
+    Ingest {
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+        /// Move the files instead of copying them
+        #[arg(long = "move")]
+        move_files: bool,
+    },
+    /// Runs the extractors and reports how long they took and what failed
+    Extract,
+    /// Runs a query, e.g. '(#image, ?time) match [{ #image, :"time/creation", ?time }]'
+    Query { query: String },
+    /// Interactive shell for running queries, with history and completion of attributes
+    Repl,
+    /// Serves queries, entities and blobs over HTTP as JSON and accepts new blobs
+    Serve {
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        address: String,
+    },
+    /// Counts facts, attributes, blobs and MIME types
+    Stats,
+    /// Writes all facts, or a graph of all references between entities
+    Export {
+        /// File to write to instead of stdout
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+        /// Export nodes and links for graph visualizations instead of plain facts
+        #[arg(long)]
+        graph: bool,
+    },
+    /// Writes all facts as newline delimited JSON to the database file, for `--restore`
+    Dump {
+        /// File to write to instead of the configured database
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Builds directories of images sorted by camera and by date
+    Hierarchy {
+        /// Create empty files instead of copying the images
+        #[arg(long)]
+        empty: bool,
+    },
+    /// Checks the storage and the extracted facts for inconsistencies
+    Fsck,
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Human,
+    Json,
+}
+
+impl Format {
+    pub fn print(&self, report: &(impl fmt::Display + Serialize)) -> Result<(), Box<dyn Error>> {
+        let mut stdout = io::stdout().lock();
+
+        match self {
+            Format::Human => write!(stdout, "{report}")?,
+            Format::Json => {
+                serde_json::to_writer_pretty(&mut stdout, report)?;
+                writeln!(stdout)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// Runs a command, the exit code signals whether `fsck` found problems
+pub fn run(cli: Cli) -> Result<ExitCode, Box<dyn Error>> {
+    let config = Config::load_from(cli.overrides)?;
+    let format = cli.format;
+    let build = |config: &Config| build(config, cli.restore);
+
+    match cli.command {
+        Command::Ingest { paths, move_files } => {
+            let mode = match move_files {
+                true => ImportMode::Move,
+                false => ImportMode::Copy,
+            };
+
+            format.print(&ingest(&config, &paths, mode)?)?;
+        }
+        Command::Extract => {
+            let (handle, timings) = build(&config)?;
+            format.print(&ExtractReport::new(&handle, timings))?;
+        }
+        Command::Query { query } => {
+            let query = Query::parse(&query)?;
+            let (handle, _) = build(&config)?;
+            format.print(&QueryReport::new(&query, &handle).with_labels(&handle))?;
+        }
+        Command::Repl => {
+            let (handle, _) = build(&config)?;
+            repl::run(&handle, format, &config.cache.join("repl_history"))?;
+        }
+        Command::Serve { address } => serve::Server::new(&config, cli.restore)?.run(&address)?,
+        Command::Stats => {
+            let (handle, _) = build(&config)?;
+            format.print(&StatsReport::new(&handle))?;
+        }
+        Command::Export { output, graph } => {
+            let (handle, _) = build(&config)?;
+            let writer: Box<dyn Write> = match output {
+                Some(path) => Box::new(File::create(path)?),
+                None => Box::new(io::stdout().lock()),
+            };
+            let mut writer = BufWriter::new(writer);
+
+            match graph {
+                true => crate::graph_export::export_graph(&handle, &mut writer)?,
+                false => export(&handle, format, &mut writer)?,
+            }
+
+            writer.flush()?;
+        }
+        Command::Dump { output } => {
+            let (handle, _) = build(&config)?;
+            let path = output.unwrap_or_else(|| config.database.clone());
+            format.print(&dump(&handle, path)?)?;
+        }
+        Command::Hierarchy { empty } => {
+            let (mut handle, _) = build(&config)?;
+            let report = hierarchy::build_hierarchy(&mut handle, &config.hierarchy, empty)?;
+            format.print(&report)?;
+        }
+        Command::Fsck => {
+            let (handle, _) = build(&config)?;
+            let report = fsck::check(&handle)?;
+            format.print(&report)?;
+
+            if !report.problems.is_empty() {
+                return Ok(ExitCode::FAILURE);
+            }
+        }
+    }
+
+    Ok(ExitCode::SUCCESS)
+}
+
+fn ingest(
+    config: &Config,
+    paths: &[PathBuf],
+    mode: ImportMode,
+) -> Result<IngestReport, Box<dyn Error>> {
+    let (database, _) = Database::new();
+    let mut handle = config.handle(database)?;
+    let mut report = IngestReport::default();
+
+    for path in paths {
+        for entry in WalkDir::new(path).sort_by_file_name() {
+            let entry = entry?;
+
+            if entry.file_type().is_file() {
+                let entity = handle.import(entry.path(), mode)?;
+                report.ingested.push(Ingested {
+                    path: entry.path().to_owned(),
+                    entity: entity.0,
+                });
+            }
+        }
+    }
+
+    append_names(&handle, &config.database)?;
+    Ok(report)
+}
+
+/// Appends the `blob/name` facts to the database file, which is created if necessary. Files
+/// ingested again end up in there twice, importing skips such duplicates.
+fn append_names(handle: &Handle, path: &Path) -> Result<(), Box<dyn Error>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let file = OpenOptions::new().create(true).append(true).open(path)?;
+    let mut writer = BufWriter::new(file);
+    let name = Attribute::from("blob/name");
+
+    for (entity, _, values) in handle.eav.scan().filter(|(_, a, _)| *a == &name) {
+        for value in values {
+            serde_json::to_writer(
+                &mut writer,
+                &Fact::new(entity.clone(), name.clone(), value.clone()),
+            )?;
+            writer.write_all(b"\n")?;
+        }
+    }
+
+    writer.flush()?;
+    Ok(())
+}
+
+/// Facts inserted into the database, see [`Database::new`]
+type WriteLog = mpsc::Receiver<(Entity, Attribute, Value)>;
+
+/// Creates the database, filled with the facts from the database file when restoring. The
+/// names blobs were ingested under are always taken from it, nothing else knows about them.
+pub fn open(config: &Config, restore: bool) -> Result<(Handle, WriteLog), Box<dyn Error>> {
+    let (mut database, write_log) = Database::new();
+
+    if restore {
+        let file = File::open(&config.database)
+            .map_err(|e| format!("Cannot restore {}: {e}", config.database.display()))?;
+        database.import(BufReader::new(file))?;
+    } else if let Ok(file) = File::open(&config.database) {
+        let name = Attribute::from("blob/name");
+        database.import_where(BufReader::new(file), |fact| fact.attribute == name)?;
+    }
+
+    let mut handle = config.handle(database)?;
+    if restore {
+        retry_failures(config, &mut handle);
+    }
+
+    Ok((handle, write_log))
+}
+
+/// Retracts the failures of a restored database, so fixed extractors get another chance.
+/// Blobs they are about are retracted as well, which makes the loader pick them up as new.
+fn retry_failures(config: &Config, handle: &mut Handle) {
+    let reloaded = config.extractors.iter().any(|name| name == "loader");
+
+    for failure in FailureReport::load(handle).failures {
+        match &failure.entity {
+            Some(entity) if reloaded && handle.known(entity, "blob/size").is_some() => {
+                BlobLoader::remove(handle, entity);
+            }
+            _ => handle.retract_entity(&failure.id()),
+        }
+    }
+}
+
+/// Runs all configured extractors over the storage, keeping track of the time spent in each
+pub fn build(config: &Config, restore: bool) -> Result<(Handle, Vec<Timing>), Box<dyn Error>> {
+    let (mut handle, write_log) = open(config, restore)?;
+
+    let mut timings = Vec::new();
+    let mut extractors: Vec<Box<dyn Extractor>> = Vec::new();
+
+    for extractor in config.extractors()? {
+        let timing = Rc::new(Cell::new(Timing::default()));
+        timings.push((extractor.name().to_owned(), timing.clone()));
+        extractors.push(Box::new(Timed {
+            inner: extractor,
+            timing,
+        }));
+    }
+
+    // Watching loaders would keep the pipeline alive forever, commands only need a snapshot
+    init_extractors(&mut handle, &mut extractors);
+    drain_write_log(
+        &write_log,
+        &mut handle,
+        &mut extractors,
+        &RetryPolicy::default(),
+    )?;
+
+    let timings = timings
+        .into_iter()
+        .map(|(name, timing)| Timing {
+            extractor: name,
+            ..timing.take()
+        })
+        .collect();
+
+    Ok((handle, timings))
+}
+
+/// Writes the dump next to the target first, so an interrupted dump never replaces a good one
+fn dump(handle: &Handle, path: PathBuf) -> Result<DumpReport, Box<dyn Error>> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    let mut temp = path.clone().into_os_string();
+    temp.push(".tmp");
+
+    let facts = handle.export(BufWriter::new(File::create(&temp)?))?;
+    fs::rename(&temp, &path)?;
+
+    Ok(DumpReport { path, facts })
+}
+
+fn export(handle: &Handle, format: Format, writer: &mut impl Write) -> Result<(), Box<dyn Error>> {
+    let mut facts: Vec<_> = handle
+        .eav
+        .scan()
+        .flat_map(|(entity, attribute, values)| {
+            values.iter().map(move |value| (entity, attribute, value))
+        })
+        .collect();
+    facts.sort_by(|a, b| (&a.0 .0, &a.1 .0).cmp(&(&b.0 .0, &b.1 .0)));
+
+    match format {
+        Format::Human => {
+            for (entity, attribute, value) in facts {
+                writeln!(
+                    writer,
+                    "{}\t{}\t{}",
+                    entity.0,
+                    attribute.0,
+                    ReportValue(value.clone())
+                )?;
+            }
+        }
+        Format::Json => {
+            let facts: Vec<_> = facts
+                .into_iter()
+                .map(|(entity, attribute, value)| {
+                    Fact::new(entity.clone(), attribute.clone(), value.clone())
+                })
+                .collect();
+
+            serde_json::to_writer_pretty(&mut *writer, &facts)?;
+            writeln!(writer)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Extractor wrapper measuring the time spent in the wrapped one
+struct Timed {
+    inner: Box<dyn Extractor>,
+    timing: Rc<Cell<Timing>>,
+}
+
+impl Timed {
+    fn measure<T>(&mut self, f: impl FnOnce(&mut dyn Extractor) -> T, init: bool) -> T {
+        let start = Instant::now();
+        let result = f(self.inner.as_mut());
+        let mut timing = self.timing.take();
+
+        match init {
+            true => timing.init += start.elapsed(),
+            false => timing.run += start.elapsed(),
+        }
+
+        self.timing.set(timing);
+        result
+    }
+}
+
+impl Extractor for Timed {
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    fn init(&mut self, handle: &mut Handle) -> Result<(), Box<dyn Error>> {
+        self.measure(|inner| inner.init(handle), true)
+    }
+
+    fn entry_added(
+        &mut self,
+        handle: &mut Handle,
+        entity: &Entity,
+        attribute: &Attribute,
+        value: &Value,
+    ) -> Result<(), Box<dyn Error>> {
+        self.measure(
+            |inner| inner.entry_added(handle, entity, attribute, value),
+            false,
+        )
+    }
+
+    fn is_live(&self) -> bool {
+        self.inner.is_live()
+    }
+
+    fn poll(&mut self, handle: &mut Handle) -> Result<(), Box<dyn Error>> {
+        self.measure(|inner| inner.poll(handle), false)
+    }
+}
+
+/// Time spent in an extractor
+#[derive(Debug, Default, Clone, Serialize)]
+pub struct Timing {
+    pub extractor: String,
+    #[serde(serialize_with = "report::milliseconds")]
+    pub init: Duration,
+    #[serde(serialize_with = "report::milliseconds")]
+    pub run: Duration,
+}
+
+#[cfg(test)]
+mod does {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn parse_commands_and_global_flags() {
+        let cli = Cli::parse_from([
+            "firn",
+            "query",
+            "[{ #e, :a, ?v }]",
+            "--format",
+            "json",
+            "--extractors",
+            "loader,mime",
+        ]);
+
+        assert!(matches!(cli.command, Command::Query { .. }));
+        assert_eq!(cli.format, Format::Json);
+        assert_eq!(
+            cli.overrides.extractors,
+            Some(vec!["loader".into(), "mime".into()])
+        );
+    }
+
+    #[test]
+    fn ingest_and_build_the_database() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let source = dir.path().join("source");
+        fs::create_dir_all(source.join("nested"))?;
+        fs::write(source.join("a.txt"), "hello")?;
+        fs::write(source.join("nested/b.txt"), "world")?;
+
+        let config = Config::load_with(&Overrides {
+            storage: Some(dir.path().join("storage")),
+            database: Some(dir.path().join("database")),
+            extractors: Some(vec!["loader".into(), "mime".into()]),
+            ..Default::default()
+        })?;
+
+        let ingested = ingest(&config, &[source], ImportMode::Copy)?;
+        assert_eq!(ingested.ingested.len(), 2);
+
+        let (handle, timings) = build(&config, false)?;
+        let names: Vec<_> = timings.iter().map(|t| t.extractor.as_str()).collect();
+        assert_eq!(names, vec!["loader", "mime"]);
+
+        let query = Query::parse(r#"(#blob, ?mime) match [{ #blob, :"type/mime", ?mime }]"#)?;
+        let report = QueryReport::new(&query, &handle);
+        assert_eq!(report.rows.len(), 2);
+
+        let stats = StatsReport::new(&handle);
+        assert_eq!(stats.blobs, 2);
+        assert_eq!(stats.blob_bytes, 10);
+
+        let mut exported = Vec::new();
+        export(&handle, Format::Human, &mut exported)?;
+        let exported = String::from_utf8(exported)?;
+        let blob = &ingested.ingested[0].entity;
+        assert!(exported.contains(&format!("{blob}\tblob/size\t5")));
+
+        // Restoring a dump yields the same facts without extracting anything again
+        let report = dump(&handle, config.database.clone())?;
+        assert_eq!(report.facts, handle.len());
+
+        let (restored, write_log) = open(&config, true)?;
+        assert_eq!(restored.len(), handle.len());
+        assert!(write_log.try_recv().is_err());
+
+        let (rebuilt, _) = build(&config, true)?;
+        assert_eq!(rebuilt.len(), handle.len());
+
+        Ok(())
+    }
+
+    #[test]
+    fn keep_names_of_ingested_files() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let source = dir.path().join("source");
+        fs::create_dir_all(&source)?;
+        fs::write(source.join("a.txt"), "hello")?;
+
+        let config = Config::load_with(&Overrides {
+            storage: Some(dir.path().join("storage")),
+            database: Some(dir.path().join("database")),
+            extractors: Some(vec!["loader".into(), "mime".into()]),
+            ..Default::default()
+        })?;
+
+        ingest(&config, &[source], ImportMode::Copy)?;
+
+        let query = Query::parse(
+            r#"(#blob, ?name, ?mime) match [{ #blob, :"blob/name", ?name }, { #blob, :"type/mime", ?mime }]"#,
+        )?;
+        let named = |handle: &Handle| QueryReport::new(&query, handle).rows;
+
+        let (handle, _) = build(&config, false)?;
+        assert_eq!(named(&handle).len(), 1);
+        assert_eq!(
+            handle
+                .ave
+                .values(&"blob/name".into(), &"a.txt".into())
+                .count(),
+            1
+        );
+
+        // Dumping a fresh build keeps the names as well
+        dump(&handle, config.database.clone())?;
+        let (rebuilt, _) = build(&config, false)?;
+        assert_eq!(named(&rebuilt).len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn retry_failures_after_restoring() -> Result<(), Box<dyn Error>> {
+        struct Flaky(bool);
+
+        impl Extractor for Flaky {
+            fn entry_added(
+                &mut self,
+                handle: &mut Handle,
+                entity: &Entity,
+                attribute: &Attribute,
+                _: &Value,
+            ) -> Result<(), Box<dyn Error>> {
+                if attribute.0 == "blob/size" {
+                    if self.0 {
+                        return Err("broken".into());
+                    }
+                    handle.insert(entity.clone(), "test/fixed", "yes");
+                }
+
+                Ok(())
+            }
+        }
+
+        let dir = tempfile::tempdir()?;
+        let config = Config::load_with(&Overrides {
+            storage: Some(dir.path().join("storage")),
+            database: Some(dir.path().join("database")),
+            extractors: Some(vec!["loader".into()]),
+            ..Default::default()
+        })?;
+        fs::create_dir_all(&config.storage)?;
+        fs::write(config.storage.join("a.txt"), "a")?;
+
+        let run = |broken: bool, restore: bool| -> Result<Handle, Box<dyn Error>> {
+            let (mut handle, write_log) = open(&config, restore)?;
+            let mut extractors = crate::make_extractors![BlobLoader::new(), Flaky(broken)];
+            init_extractors(&mut handle, &mut extractors);
+            drain_write_log(
+                &write_log,
+                &mut handle,
+                &mut extractors,
+                &RetryPolicy::none(),
+            )?;
+            Ok(handle)
+        };
+
+        let handle = run(true, false)?;
+        assert_eq!(FailureReport::load(&handle).failures.len(), 1);
+        dump(&handle, config.database.clone())?;
+
+        let handle = run(false, true)?;
+        assert!(FailureReport::load(&handle).is_empty());
+        assert_eq!(
+            handle
+                .known(&Entity::from("a.txt"), "test/fixed")
+                .as_deref(),
+            Some("yes")
+        );
+
+        Ok(())
+    }
+}
@@ -0,0 +1,237 @@
+//! Interactive shell for exploring a database without recompiling `query!` blocks
+
+use super::{Format, QueryReport};
+use crate::{db::Query, handle::Handle};
+use rustyline::{
+    completion::{Completer, Pair},
+    error::ReadlineError,
+    highlight::Highlighter,
+    hint::Hinter,
+    history::FileHistory,
+    validate::Validator,
+    Context, Editor, Helper,
+};
+use std::{collections::BTreeSet, error::Error, fs, path::Path};
+
+const HELP: &str = r#"Enter a query to run it, e.g.
+
+    (#image, ?time) match [{ #image, :"time/creation", ?time }]
+
+Attribute names are completed with tab after :"
+
+Commands:
+    .attributes      lists all attributes
+    .entity <id>     shows all facts about an entity
+    .help            shows this help
+    .quit            exits, as does Ctrl-D
+"#;
+
+pub fn run(handle: &Handle, format: Format, history: &Path) -> Result<(), Box<dyn Error>> {
+    let mut editor: Editor<AttributeCompleter, FileHistory> = Editor::new()?;
+    editor.set_helper(Some(AttributeCompleter::new(handle)));
+
+    // Missing history on the first start is fine
+    editor.load_history(history).ok();
+
+    println!("{} triplets loaded, .help for help", handle.len());
+
+    loop {
+        let line = match editor.readline("firn> ") {
+            Ok(line) => line,
+            Err(ReadlineError::Interrupted) => continue,
+            Err(ReadlineError::Eof) => break,
+            Err(e) => return Err(e.into()),
+        };
+
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+
+        editor.add_history_entry(line)?;
+
+        match evaluate(handle, format, line) {
+            Ok(Some(output)) => print!("{output}"),
+            Ok(None) => break,
+            Err(e) => eprintln!("Error: {e}"),
+        }
+    }
+
+    if let Some(parent) = history.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    editor.save_history(history)?;
+
+    Ok(())
+}
+
+/// Runs a single line of input, returning what to print or `None` to quit
+fn evaluate(handle: &Handle, format: Format, line: &str) -> Result<Option<String>, Box<dyn Error>> {
+    let (command, argument) = line.split_once(' ').unwrap_or((line, ""));
+
+    let output = match command {
+        ".quit" | ".exit" => return Ok(None),
+        ".help" => HELP.to_owned(),
+        ".attributes" => AttributeCompleter::new(handle)
+            .attributes
+            .iter()
+            .map(|attribute| format!("{attribute}\n"))
+            .collect(),
+        ".entity" => {
+            let entity = argument.trim().trim_start_matches('#').trim_matches('"');
+            if entity.is_empty() {
+                return Err("Missing entity, e.g. .entity #abc".into());
+            }
+
+            // Escape the ID so it can be used as a constant
+            let entity = entity.replace('\\', "\\\\").replace('"', "\\\"");
+            let query = Query::parse(&format!(r#"[{{ #"{entity}", :attribute, ?value }}]"#))?;
+            render(handle, format, &query)?
+        }
+        _ if command.starts_with('.') => {
+            return Err(format!("Unknown command {command}, see .help").into())
+        }
+        _ => render(handle, format, &Query::parse(line)?)?,
+    };
+
+    Ok(Some(output))
+}
+
+fn render(handle: &Handle, format: Format, query: &Query) -> Result<String, Box<dyn Error>> {
+    let report = QueryReport::new(query, handle).with_labels(handle);
+
+    Ok(match format {
+        Format::Human => report.to_string(),
+        Format::Json => serde_json::to_string_pretty(&report)? + "\n",
+    })
+}
+
+/// Completes attribute constants with the attributes that are in the database
+struct AttributeCompleter {
+    attributes: Vec<String>,
+}
+
+impl AttributeCompleter {
+    fn new(handle: &Handle) -> Self {
+        let attributes: BTreeSet<_> = handle
+            .ave
+            .scan()
+            .map(|(attribute, _, _)| attribute.0.clone())
+            .collect();
+
+        Self {
+            attributes: attributes.into_iter().collect(),
+        }
+    }
+
+    /// Start of the replaced text and the possible replacements
+    fn candidates(&self, line: &str, pos: usize) -> (usize, Vec<String>) {
+        let line = &line[..pos];
+        let Some(start) = line.rfind(":\"") else {
+            return (pos, Vec::new());
+        };
+
+        let prefix = &line[start + 2..];
+        if prefix.contains('"') {
+            return (pos, Vec::new());
+        }
+
+        let candidates = self
+            .attributes
+            .iter()
+            .filter(|attribute| attribute.starts_with(prefix))
+            .map(|attribute| format!(":\"{attribute}\""))
+            .collect();
+
+        (start, candidates)
+    }
+}
+
+impl Completer for AttributeCompleter {
+    type Candidate = Pair;
+
+    fn complete(
+        &self,
+        line: &str,
+        pos: usize,
+        _: &Context<'_>,
+    ) -> rustyline::Result<(usize, Vec<Pair>)> {
+        let (start, candidates) = self.candidates(line, pos);
+
+        let pairs = candidates
+            .into_iter()
+            .map(|replacement| Pair {
+                display: replacement[2..replacement.len() - 1].to_owned(),
+                replacement,
+            })
+            .collect();
+
+        Ok((start, pairs))
+    }
+}
+
+impl Hinter for AttributeCompleter {
+    type Hint = String;
+}
+
+impl Highlighter for AttributeCompleter {}
+
+impl Validator for AttributeCompleter {}
+
+impl Helper for AttributeCompleter {}
+
+#[cfg(test)]
+mod does {
+    use super::*;
+    use crate::db::{Database, Entity};
+    use std::path::PathBuf;
+
+    fn handle() -> Handle {
+        let (db, _) = Database::new();
+        let mut handle = Handle::new(db, PathBuf::from("storage"));
+        handle.insert("a.jpg", "time/creation", "2023-01-14T18:30:05Z");
+        handle.insert("a.jpg", "image/camera", Entity::from("pixel"));
+        handle.insert("pixel", "text/label", "Pixel 7");
+        handle.insert("pixel", "device/manufacturer", "Google");
+        handle
+    }
+
+    #[test]
+    fn complete_attribute_names() {
+        let completer = AttributeCompleter::new(&handle());
+
+        let line = r#"[{ #e, :"ti"#;
+        assert_eq!(
+            completer.candidates(line, line.len()),
+            (7, vec![r#":"time/creation""#.to_owned()])
+        );
+
+        let line = r#"[{ #e, :""#;
+        assert_eq!(completer.candidates(line, line.len()).1.len(), 4);
+
+        let line = r#"[{ #e, :"time/creation", ?"#;
+        assert!(completer.candidates(line, line.len()).1.is_empty());
+    }
+
+    #[test]
+    fn show_labels_of_entities() -> Result<(), Box<dyn Error>> {
+        let handle = handle();
+
+        let output = evaluate(
+            &handle,
+            Format::Human,
+            r#"(#camera) match [{ #image, :"image/camera", #camera }]"#,
+        )?
+        .unwrap();
+        assert!(output.contains("Pixel 7 (#pixel)"));
+
+        let output = evaluate(&handle, Format::Human, ".entity #pixel")?.unwrap();
+        assert!(output.contains("device/manufacturer  Google"));
+        assert!(output.contains("2 results"));
+
+        assert!(evaluate(&handle, Format::Human, ".quit")?.is_none());
+        assert!(evaluate(&handle, Format::Human, ".unknown").is_err());
+
+        Ok(())
+    }
+}
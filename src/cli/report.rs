@@ -0,0 +1,362 @@
+//! Results of the commands, printed either as text or as JSON
+
+use super::Timing;
+use crate::{
+    db::{Attribute, Query, Value},
+    extractor::{FailureReport, MimeInfer},
+    handle::Handle,
+};
+use serde::{Serialize, Serializer};
+use std::{
+    collections::{BTreeMap, HashSet},
+    fmt,
+    path::PathBuf,
+    time::Duration,
+};
+
+/// Value as it is shown to users, references are written as `#entity` in text to tell them
+/// apart from data
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+#[serde(transparent)]
+pub struct ReportValue(pub Value);
+
+impl fmt::Display for ReportValue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.0 {
+            Value::Data(data) => write!(f, "{data}"),
+            Value::Reference(entity) => write!(f, "#{}", entity.0),
+        }
+    }
+}
+
+pub(super) fn milliseconds<S: Serializer>(
+    duration: &Duration,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u128(duration.as_millis())
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct IngestReport {
+    pub ingested: Vec<Ingested>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Ingested {
+    pub path: PathBuf,
+    pub entity: String,
+}
+
+impl fmt::Display for IngestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for ingested in &self.ingested {
+            writeln!(f, "{} -> {}", ingested.path.display(), ingested.entity)?;
+        }
+
+        writeln!(f, "Ingested {} files", self.ingested.len())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct ExtractReport {
+    pub timings: Vec<Timing>,
+    pub triplets: usize,
+    pub failures: Vec<FailureEntry>,
+    #[serde(skip)]
+    report: FailureReport,
+}
+
+#[derive(Debug, Serialize)]
+pub struct FailureEntry {
+    pub extractor: String,
+    pub entity: Option<String>,
+    pub kind: String,
+    pub message: String,
+}
+
+impl ExtractReport {
+    pub fn new(handle: &Handle, timings: Vec<Timing>) -> Self {
+        let report = FailureReport::load(handle);
+        let failures = report
+            .failures
+            .iter()
+            .map(|failure| FailureEntry {
+                extractor: failure.extractor.clone(),
+                entity: failure.entity.as_ref().map(|entity| entity.0.clone()),
+                kind: failure.kind.to_string(),
+                message: failure.message.clone(),
+            })
+            .collect();
+
+        Self {
+            timings,
+            triplets: handle.len(),
+            failures,
+            report,
+        }
+    }
+}
+
+impl fmt::Display for ExtractReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for timing in &self.timings {
+            writeln!(
+                f,
+                "{:0>4}ms -> {:0>4}ms for {}",
+                timing.init.as_millis(),
+                timing.run.as_millis(),
+                timing.extractor
+            )?;
+        }
+
+        writeln!(f, "{} triplets stored", self.triplets)?;
+        write!(f, "{}", self.report)
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct QueryReport {
+    pub variables: Vec<String>,
+    pub rows: Vec<Vec<Option<ReportValue>>>,
+    /// `text/label` of the referenced entities, if resolved
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+}
+
+impl QueryReport {
+    pub fn new(query: &Query, handle: &Handle) -> Self {
+        Self {
+            variables: query
+                .variables()
+                .iter()
+                .map(|variable| variable.name().to_owned())
+                .collect(),
+            rows: query
+                .run(handle)
+                .into_iter()
+                .map(|row| row.into_iter().map(|v| v.map(ReportValue)).collect())
+                .collect(),
+            labels: BTreeMap::new(),
+        }
+    }
+
+    /// Looks up the `text/label` of all entities in the results, which are then shown next
+    /// to their IDs
+    pub fn with_labels(mut self, handle: &Handle) -> Self {
+        let label = Attribute::from("text/label");
+
+        for value in self.rows.iter().flatten().flatten() {
+            if let Value::Reference(entity) = &value.0 {
+                let found = handle.get(entity, &label).find_map(|value| match value {
+                    Value::Data(label) => Some(label.clone()),
+                    Value::Reference(_) => None,
+                });
+
+                if let Some(found) = found {
+                    self.labels.insert(entity.0.clone(), found);
+                }
+            }
+        }
+
+        self
+    }
+}
+
+impl fmt::Display for QueryReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let cells: Vec<Vec<String>> = self
+            .rows
+            .iter()
+            .map(|row| {
+                row.iter()
+                    .map(|value| match value {
+                        Some(ReportValue(Value::Reference(entity))) => {
+                            match self.labels.get(&entity.0) {
+                                Some(label) => format!("{label} (#{})", entity.0),
+                                None => format!("#{}", entity.0),
+                            }
+                        }
+                        Some(value) => value.to_string(),
+                        None => String::new(),
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let widths: Vec<usize> = self
+            .variables
+            .iter()
+            .enumerate()
+            .map(|(i, name)| {
+                cells
+                    .iter()
+                    .map(|row| row[i].chars().count())
+                    .chain([name.chars().count()])
+                    .max()
+                    .unwrap_or_default()
+            })
+            .collect();
+
+        let line = |f: &mut fmt::Formatter, cells: &[String]| {
+            let line: Vec<String> = cells
+                .iter()
+                .zip(&widths)
+                .map(|(cell, width)| format!("{cell:width$}"))
+                .collect();
+            writeln!(f, "{}", line.join("  ").trim_end())
+        };
+
+        line(f, &self.variables)?;
+        for row in &cells {
+            line(f, row)?;
+        }
+
+        writeln!(f, "{} results", self.rows.len())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsReport {
+    pub triplets: usize,
+    pub entities: usize,
+    pub blobs: usize,
+    pub blob_bytes: u64,
+    pub failures: usize,
+    pub attributes: BTreeMap<String, usize>,
+    pub mime_types: BTreeMap<String, usize>,
+}
+
+impl StatsReport {
+    pub fn new(handle: &Handle) -> Self {
+        let mut entities = HashSet::new();
+        let mut attributes = BTreeMap::new();
+        let mut mime_types = BTreeMap::new();
+        let mut blobs = 0;
+        let mut blob_bytes = 0;
+
+        for (entity, attribute, values) in handle.eav.scan() {
+            entities.insert(entity);
+            *attributes.entry(attribute.0.clone()).or_default() += values.len();
+
+            if attribute.0 == "blob/size" {
+                blobs += 1;
+                blob_bytes += values
+                    .iter()
+                    .filter_map(|value| match value {
+                        Value::Data(size) => size.parse::<u64>().ok(),
+                        Value::Reference(_) => None,
+                    })
+                    .sum::<u64>();
+            }
+
+            if *attribute == MimeInfer::attribute() {
+                for value in values {
+                    if let Value::Data(mime) = value {
+                        *mime_types.entry(mime.clone()).or_default() += 1;
+                    }
+                }
+            }
+        }
+
+        Self {
+            triplets: handle.len(),
+            entities: entities.len(),
+            blobs,
+            blob_bytes,
+            failures: FailureReport::load(handle).failures.len(),
+            attributes,
+            mime_types,
+        }
+    }
+}
+
+impl fmt::Display for StatsReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "{: >10} triplets", self.triplets)?;
+        writeln!(f, "{: >10} entities", self.entities)?;
+        writeln!(f, "{: >10} blobs ({} bytes)", self.blobs, self.blob_bytes)?;
+        writeln!(f, "{: >10} extractor failures", self.failures)?;
+
+        writeln!(f, "\nAttributes")?;
+        for (attribute, count) in &self.attributes {
+            writeln!(f, "{count: >10} {attribute}")?;
+        }
+
+        writeln!(f, "\nMIME types")?;
+        for (mime, count) in &self.mime_types {
+            writeln!(f, "{count: >10} {mime}")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct DumpReport {
+    pub path: PathBuf,
+    pub facts: usize,
+}
+
+impl fmt::Display for DumpReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Dumped {} facts to {}", self.facts, self.path.display())
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct HierarchyReport {
+    pub root: PathBuf,
+    pub images: usize,
+}
+
+impl fmt::Display for HierarchyReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "Sorted {} images into {}",
+            self.images,
+            self.root.display()
+        )
+    }
+}
+
+#[derive(Debug, Default, Serialize)]
+pub struct FsckReport {
+    pub problems: Vec<Problem>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Problem {
+    pub kind: ProblemKind,
+    /// Entity or path the problem is about
+    pub subject: String,
+    pub message: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ProblemKind {
+    UnavailableRoot,
+    MissingBlob,
+    DanglingReference,
+    OrphanedDerivedBlob,
+    ExtractorFailure,
+}
+
+impl fmt::Display for FsckReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.problems.is_empty() {
+            return writeln!(f, "No problems found");
+        }
+
+        for problem in &self.problems {
+            writeln!(
+                f,
+                "{:?}: {}: {}",
+                problem.kind, problem.subject, problem.message
+            )?;
+        }
+
+        writeln!(f, "{} problems found", self.problems.len())
+    }
+}
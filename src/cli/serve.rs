@@ -0,0 +1,418 @@
+//! HTTP server answering queries with JSON, for the web frontend
+//!
+//! - `GET /query?q=<query>` or `POST /query` with the query as body runs a query
+//! - `GET /entity/<id>` lists all facts about an entity
+//! - `GET /blob/<id>` downloads the contents of a blob
+//! - `POST /ingest?name=<file name>` stores the body as a new blob and runs the extractors on it
+
+use super::{QueryReport, WriteLog};
+use crate::{
+    config::Config,
+    db::{Entity, Fact, Query, Value},
+    drain_write_log,
+    extractor::{Extractor, MimeInfer, RetryPolicy},
+    handle::Handle,
+    init_extractors,
+};
+use serde::Serialize;
+use serde_json::json;
+use std::{
+    error::Error,
+    fs::File,
+    io::{self, Read},
+    time::Duration,
+};
+use tiny_http::{Header, Method, Request, Response, ResponseBox};
+
+/// How often watching extractors are polled while no requests come in
+const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// Largest request body accepted, e.g. for blobs uploaded to `/ingest`
+const MAX_BODY_SIZE: u64 = 1 << 30;
+
+pub struct Server {
+    handle: Handle,
+    write_log: WriteLog,
+    extractors: Vec<Box<dyn Extractor>>,
+    max_body_size: u64,
+}
+
+/// Response before it is turned into HTTP
+#[derive(Debug)]
+enum Reply {
+    Json(u16, serde_json::Value),
+    Blob(File, String),
+}
+
+impl Reply {
+    fn ok(body: &impl Serialize) -> Result<Self, Box<dyn Error>> {
+        Ok(Reply::Json(200, serde_json::to_value(body)?))
+    }
+
+    fn error(status: u16, message: impl ToString) -> Self {
+        Reply::Json(status, json!({ "error": message.to_string() }))
+    }
+
+    fn status(&self) -> u16 {
+        match self {
+            Reply::Json(status, _) => *status,
+            Reply::Blob(..) => 200,
+        }
+    }
+
+    fn into_response(self) -> ResponseBox {
+        let (response, content_type) = match self {
+            Reply::Json(status, body) => (
+                Response::from_data(body.to_string())
+                    .with_status_code(status)
+                    .boxed(),
+                "application/json".to_owned(),
+            ),
+            Reply::Blob(file, mime) => (Response::from_file(file).boxed(), mime),
+        };
+
+        match Header::from_bytes("Content-Type", content_type) {
+            Ok(header) => response.with_header(header),
+            Err(_) => response,
+        }
+    }
+}
+
+impl Server {
+    /// Builds the database by running the configured extractors, which are kept around to
+    /// process blobs ingested through the server
+    pub fn new(config: &Config, restore: bool) -> Result<Self, Box<dyn Error>> {
+        let (handle, write_log) = super::open(config, restore)?;
+        let mut server = Self {
+            handle,
+            write_log,
+            extractors: config.extractors()?,
+            max_body_size: MAX_BODY_SIZE,
+        };
+
+        init_extractors(&mut server.handle, &mut server.extractors);
+        server.process()?;
+
+        Ok(server)
+    }
+
+    pub fn run(&mut self, address: &str) -> Result<(), Box<dyn Error>> {
+        let server = tiny_http::Server::http(address).map_err(|e| e.to_string())?;
+        eprintln!("Listening on http://{}", server.server_addr());
+
+        loop {
+            // Wake up regularly to pick up changes seen by watching extractors
+            let Some(mut request) = server.recv_timeout(POLL_INTERVAL)? else {
+                if self.extractors.iter().any(|e| e.is_live()) {
+                    self.process()?;
+                }
+                continue;
+            };
+
+            let reply = self.respond(&mut request);
+            eprintln!("{} {} {}", request.method(), request.url(), reply.status());
+
+            if let Err(e) = request.respond(reply.into_response()) {
+                eprintln!("Failed to send response: {e}");
+            }
+        }
+    }
+
+    fn respond(&mut self, request: &mut Request) -> Reply {
+        let method = request.method().clone();
+        let url = request.url().to_owned();
+
+        // Bodies without a length are still capped while reading them
+        if request
+            .body_length()
+            .is_some_and(|length| length as u64 > self.max_body_size)
+        {
+            return Reply::error(413, "Request body too large");
+        }
+
+        self.route(&method, &url, request.as_reader())
+            .unwrap_or_else(|e| Reply::error(500, e))
+    }
+
+    fn route(
+        &mut self,
+        method: &Method,
+        url: &str,
+        body: &mut dyn Read,
+    ) -> Result<Reply, Box<dyn Error>> {
+        let (path, query) = url.split_once('?').unwrap_or((url, ""));
+        let mut body = Capped(body.take(self.max_body_size));
+        let parameter = |name: &str| -> Result<Option<String>, Box<dyn Error>> {
+            for pair in query.split('&') {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                if decode(key)? == name {
+                    return Ok(Some(decode(value)?));
+                }
+            }
+
+            Ok(None)
+        };
+
+        match (method, path) {
+            (Method::Get, "/query") => match parameter("q")? {
+                Some(query) => Ok(self.query(&query)),
+                None => Ok(Reply::error(400, "Missing query parameter q")),
+            },
+            (Method::Post, "/query") => {
+                let mut query = String::new();
+                match body.read_to_string(&mut query) {
+                    Ok(_) => Ok(self.query(&query)),
+                    Err(e) if is_too_large(&e) => Ok(Reply::error(413, e)),
+                    Err(e) => Err(e.into()),
+                }
+            }
+            (Method::Get, _) if path.starts_with("/entity/") => {
+                self.entity(&Entity::from(decode(&path["/entity/".len()..])?))
+            }
+            (Method::Get, _) if path.starts_with("/blob/") => {
+                Ok(self.blob(&Entity::from(decode(&path["/blob/".len()..])?)))
+            }
+            (Method::Post, "/ingest") => {
+                let name = parameter("name")?;
+                let entity = match self.handle.ingest(&mut body, name.as_deref()) {
+                    Ok(entity) => entity,
+                    Err(e) if is_too_large(e.as_ref()) => return Ok(Reply::error(413, e)),
+                    Err(e) => return Err(e),
+                };
+                self.process()?;
+
+                Ok(Reply::Json(201, json!({ "entity": entity.0 })))
+            }
+            (_, "/query" | "/ingest") => Ok(Reply::error(405, "Method not allowed")),
+            _ => Ok(Reply::error(404, "Not found")),
+        }
+    }
+
+    fn query(&self, query: &str) -> Reply {
+        let query = match Query::parse(query) {
+            Ok(query) => query,
+            Err(e) => return Reply::error(400, e),
+        };
+
+        Reply::ok(&QueryReport::new(&query, &self.handle).with_labels(&self.handle))
+            .unwrap_or_else(|e| Reply::error(500, e))
+    }
+
+    fn entity(&self, entity: &Entity) -> Result<Reply, Box<dyn Error>> {
+        let mut facts: Vec<_> = self
+            .handle
+            .eav
+            .get(entity)
+            .flat_map(|(attribute, values)| {
+                values
+                    .iter()
+                    .map(|value| Fact::new(entity.clone(), attribute.clone(), value.clone()))
+            })
+            .collect();
+
+        if facts.is_empty() {
+            return Ok(Reply::error(404, format!("Unknown entity {}", entity.0)));
+        }
+
+        facts.sort_by(|a, b| a.attribute.0.cmp(&b.attribute.0));
+        Reply::ok(&facts)
+    }
+
+    fn blob(&self, entity: &Entity) -> Reply {
+        if self
+            .handle
+            .get(entity, &"blob/size".into())
+            .next()
+            .is_none()
+        {
+            return Reply::error(404, format!("Unknown blob {}", entity.0));
+        }
+
+        let mime = self
+            .handle
+            .get(entity, &MimeInfer::attribute())
+            .find_map(|value| match value {
+                Value::Data(mime) => Some(mime.clone()),
+                Value::Reference(_) => None,
+            })
+            .unwrap_or_else(|| "application/octet-stream".to_owned());
+
+        match self.handle.blob_path(entity).and_then(File::open) {
+            Ok(file) => Reply::Blob(file, mime),
+            Err(e) => Reply::error(404, e),
+        }
+    }
+
+    /// Runs the extractors until everything that has been inserted is processed, without
+    /// waiting for watching extractors to stop
+    fn process(&mut self) -> Result<(), Box<dyn Error>> {
+        drain_write_log(
+            &self.write_log,
+            &mut self.handle,
+            &mut self.extractors,
+            &RetryPolicy::default(),
+        )?;
+
+        Ok(())
+    }
+}
+
+/// Request body limited to a maximum size, which fails reading bodies that are larger instead
+/// of silently truncating them
+struct Capped<R>(io::Take<R>);
+
+impl<R: Read> Read for Capped<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.0.read(buf)?;
+
+        if read == 0
+            && !buf.is_empty()
+            && self.0.limit() == 0
+            && self.0.get_mut().read(&mut [0])? > 0
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::FileTooLarge,
+                "Request body too large",
+            ));
+        }
+
+        Ok(read)
+    }
+}
+
+fn is_too_large(error: &(dyn Error + 'static)) -> bool {
+    error
+        .downcast_ref::<io::Error>()
+        .is_some_and(|e| e.kind() == io::ErrorKind::FileTooLarge)
+}
+
+/// Decodes percent encoded URL components, with `+` standing for a space
+fn decode(input: &str) -> Result<String, Box<dyn Error>> {
+    let mut bytes = Vec::with_capacity(input.len());
+    let mut input = input.bytes();
+
+    while let Some(byte) = input.next() {
+        match byte {
+            b'%' => {
+                let hex = [input.next(), input.next()];
+                let hex = match hex {
+                    [Some(high), Some(low)] => String::from_utf8(vec![high, low])?,
+                    _ => return Err("Truncated percent encoding".into()),
+                };
+                bytes.push(u8::from_str_radix(&hex, 16)?);
+            }
+            b'+' => bytes.push(b' '),
+            byte => bytes.push(byte),
+        }
+    }
+
+    Ok(String::from_utf8(bytes)?)
+}
+
+#[cfg(test)]
+mod does {
+    use super::*;
+    use crate::config::Overrides;
+    use std::io::{empty, Cursor};
+
+    fn json(reply: Reply) -> (u16, serde_json::Value) {
+        match reply {
+            Reply::Json(status, body) => (status, body),
+            Reply::Blob(..) => panic!("expected JSON"),
+        }
+    }
+
+    #[test]
+    fn decode_url_components() -> Result<(), Box<dyn Error>> {
+        assert_eq!(decode("a%2Fb+c%22")?, "a/b c\"");
+        assert!(decode("%2").is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn serve_queries_entities_and_blobs() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let config = Config::load_with(&Overrides {
+            storage: Some(dir.path().to_owned()),
+            extractors: Some(vec!["loader".into(), "mime".into()]),
+            ..Default::default()
+        })?;
+        let mut server = Server::new(&config, false)?;
+
+        let (status, body) = json(server.route(
+            &Method::Post,
+            "/ingest?name=hello%20world.txt",
+            &mut Cursor::new("hello"),
+        )?);
+        assert_eq!(status, 201);
+        let entity = body["entity"].as_str().unwrap().to_owned();
+
+        let (status, body) = json(server.route(
+            &Method::Get,
+            "/query?q=%5B%7B+%23e%2C+%3A%22blob%2Fname%22%2C+%3Fname+%7D%5D",
+            &mut empty(),
+        )?);
+        assert_eq!(status, 200);
+        assert_eq!(
+            body["rows"],
+            json!([[{ "@id": entity }, "hello world.txt"]])
+        );
+
+        let (status, body) =
+            json(server.route(&Method::Get, &format!("/entity/{entity}"), &mut empty())?);
+        assert_eq!(status, 200);
+        assert!(body
+            .as_array()
+            .unwrap()
+            .contains(&json!({ "entity": entity, "attribute": "blob/size", "value": "5" })));
+
+        let mut contents = String::new();
+        match server.route(&Method::Get, &format!("/blob/{entity}"), &mut empty())? {
+            Reply::Blob(mut file, _) => file.read_to_string(&mut contents)?,
+            reply => panic!("expected blob, got {reply:?}"),
+        };
+        assert_eq!(contents, "hello");
+
+        let mut status = |url: &str, method: Method| {
+            server
+                .route(&method, url, &mut Cursor::new("[{ #e, "))
+                .map(|reply| reply.status())
+                .ok()
+        };
+        assert_eq!(status("/query", Method::Post), Some(400));
+        assert_eq!(status("/entity/unknown", Method::Get), Some(404));
+        assert_eq!(status("/blob/unknown", Method::Get), Some(404));
+        assert_eq!(status("/ingest", Method::Get), Some(405));
+        assert_eq!(status("/nothing", Method::Get), Some(404));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_bodies_over_the_limit() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let config = Config::load_with(&Overrides {
+            storage: Some(dir.path().to_owned()),
+            extractors: Some(vec!["loader".into()]),
+            ..Default::default()
+        })?;
+        let mut server = Server::new(&config, false)?;
+        server.max_body_size = 5;
+
+        let mut status = |url: &str, body: &str| {
+            server
+                .route(&Method::Post, url, &mut Cursor::new(body.to_owned()))
+                .map(|reply| reply.status())
+                .ok()
+        };
+        assert_eq!(status("/ingest", "hello"), Some(201));
+        assert_eq!(status("/ingest", "hello world"), Some(413));
+        assert_eq!(status("/query", "[{ #e, :a, ?v }]"), Some(413));
+
+        // Nothing of the rejected upload is kept around
+        let files: Vec<_> = std::fs::read_dir(dir.path())?.collect::<Result<_, _>>()?;
+        assert_eq!(files.len(), 1);
+
+        Ok(())
+    }
+}
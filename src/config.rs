@@ -0,0 +1,494 @@
+//! Settings shared by all binaries, read from a TOML file and overridable through environment
+//! variables and command line flags (in that order of precedence, lowest first).
+//!
+//! ```toml
+//! storage = "data/storage"
+//! extractors = ["loader", "mime", "exif", "geonames"]
+//!
+//! [roots]
+//! archive = "/mnt/archive/photos"
+//!
+//! [loader]
+//! watch = true
+//!
+//! [geonames]
+//! dump = "geonames/DE.txt"
+//! hierarchy = "geonames/hierarchy.txt"
+//! features = ["S", "T"]
+//! ```
+//!
+//! Relative paths in the file are resolved against the directory the file is in.
+
+use crate::{
+    db::Database,
+    extractor::*,
+    handle::{Handle, StorageRoot},
+};
+use clap::Parser;
+use serde::Deserialize;
+use std::{
+    collections::BTreeMap,
+    env,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// File that is picked up from the working directory if no other config is given
+pub const DEFAULT_CONFIG: &str = "firn.toml";
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Primary storage root that ingested blobs end up in
+    pub storage: PathBuf,
+    /// Additional named storage roots
+    pub roots: BTreeMap<String, PathBuf>,
+    /// Where the database is persisted
+    pub database: PathBuf,
+    /// Where browsable hierarchies of the blobs are built
+    pub hierarchy: PathBuf,
+    /// Where caches, e.g. of the GeoNames index, are kept
+    pub cache: PathBuf,
+    /// Names of the extractors to run, in order
+    pub extractors: Vec<String>,
+    pub loader: LoaderConfig,
+    pub geonames: Option<GeoNamesConfig>,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoaderConfig {
+    /// Keep watching the storage roots for changes, e.g. while serving
+    pub watch: bool,
+    /// Store a `blob/hash` to tell apart changed files from touched ones
+    pub hash: bool,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct GeoNamesConfig {
+    /// Main dump, e.g. `allCountries.txt` or `DE.txt`
+    pub dump: PathBuf,
+    pub hierarchy: PathBuf,
+    pub alternate_names: Option<PathBuf>,
+    pub countries: Option<PathBuf>,
+    /// `admin1CodesASCII.txt` and/or `admin2Codes.txt`
+    #[serde(default)]
+    pub admin_codes: Vec<PathBuf>,
+    /// Feature classes indexed in addition to places
+    #[serde(default)]
+    pub features: Vec<FeatureClass>,
+    /// Maximum distance to linked places in meters
+    pub max_distance: Option<f64>,
+}
+
+/// Settings given on the command line or through the environment, which take precedence over
+/// the config file
+#[derive(Parser, Debug, Default, Clone, PartialEq)]
+pub struct Overrides {
+    /// Config file [default: firn.toml if present]
+    #[arg(long, global = true)]
+    pub config: Option<PathBuf>,
+    /// Primary storage root
+    #[arg(long, global = true)]
+    pub storage: Option<PathBuf>,
+    #[arg(long, global = true)]
+    pub database: Option<PathBuf>,
+    /// Directory browsable hierarchies are built in
+    #[arg(long, global = true)]
+    pub hierarchy: Option<PathBuf>,
+    #[arg(long, global = true)]
+    pub cache: Option<PathBuf>,
+    /// Comma separated names of the extractors to run
+    #[arg(long, global = true, value_delimiter = ',')]
+    pub extractors: Option<Vec<String>>,
+    /// GeoNames dump, e.g. DE.txt
+    #[arg(long, global = true)]
+    pub geonames: Option<PathBuf>,
+    /// GeoNames hierarchy.txt
+    #[arg(long, global = true)]
+    pub geonames_hierarchy: Option<PathBuf>,
+    /// Keep watching the storage roots for changes
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub watch: Option<bool>,
+    /// Hash blobs to detect changes to their contents
+    #[arg(long, global = true, num_args = 0..=1, default_missing_value = "true")]
+    pub hash: Option<bool>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            storage: "data/storage".into(),
+            roots: BTreeMap::new(),
+            database: "data/database".into(),
+            hierarchy: "data/hierarchy".into(),
+            cache: "data/cache".into(),
+            extractors: ["loader", "mime", "exif", "video"].map(Into::into).to_vec(),
+            loader: LoaderConfig::default(),
+            geonames: None,
+        }
+    }
+}
+
+impl Config {
+    /// Loads the config the way all binaries do: from the file given by `--config`,
+    /// `FIRN_CONFIG` or [`DEFAULT_CONFIG`], followed by environment and command line overrides
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        Self::load_from(Overrides::from_args(env::args().skip(1))?)
+    }
+
+    /// Loads the config with the given command line overrides on top of the environment
+    pub fn load_from(arguments: Overrides) -> Result<Self, Box<dyn Error>> {
+        let mut overrides = Overrides::from_env(|name| env::var(name).ok());
+        overrides.merge(arguments);
+        Self::load_with(&overrides)
+    }
+
+    pub fn load_with(overrides: &Overrides) -> Result<Self, Box<dyn Error>> {
+        let mut config = match &overrides.config {
+            Some(path) => Self::from_file(path)?,
+            None if Path::new(DEFAULT_CONFIG).exists() => Self::from_file(DEFAULT_CONFIG)?,
+            None => Self::default(),
+        };
+
+        config.apply(overrides)?;
+
+        // Blob entities use the root name as a prefix, see `Handle::blob_entity`
+        if let Some(name) = config.roots.keys().find(|name| name.contains(':')) {
+            return Err(format!("Invalid storage root name {name}, ':' is not allowed").into());
+        }
+
+        Ok(config)
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let path = path.as_ref();
+        let contents = fs::read_to_string(path)
+            .map_err(|e| format!("Failed to read config {}: {e}", path.display()))?;
+        let mut config: Self = toml::from_str(&contents)
+            .map_err(|e| format!("Invalid config {}: {e}", path.display()))?;
+
+        if let Some(base) = path.parent() {
+            config.resolve(base);
+        }
+
+        Ok(config)
+    }
+
+    fn resolve(&mut self, base: &Path) {
+        let resolve = |path: &mut PathBuf| *path = base.join(&*path);
+
+        resolve(&mut self.storage);
+        resolve(&mut self.database);
+        resolve(&mut self.hierarchy);
+        resolve(&mut self.cache);
+        self.roots.values_mut().for_each(resolve);
+
+        if let Some(geonames) = &mut self.geonames {
+            resolve(&mut geonames.dump);
+            resolve(&mut geonames.hierarchy);
+            geonames.alternate_names.iter_mut().for_each(resolve);
+            geonames.countries.iter_mut().for_each(resolve);
+            geonames.admin_codes.iter_mut().for_each(resolve);
+        }
+    }
+
+    fn apply(&mut self, overrides: &Overrides) -> Result<(), Box<dyn Error>> {
+        let Overrides {
+            config: _,
+            storage,
+            database,
+            hierarchy,
+            cache,
+            extractors,
+            geonames,
+            geonames_hierarchy,
+            watch,
+            hash,
+        } = overrides.clone();
+
+        let set = |target: &mut PathBuf, value: Option<PathBuf>| {
+            if let Some(value) = value {
+                *target = value;
+            }
+        };
+
+        set(&mut self.storage, storage);
+        set(&mut self.database, database);
+        set(&mut self.hierarchy, hierarchy);
+        set(&mut self.cache, cache);
+
+        if let Some(extractors) = extractors {
+            self.extractors = extractors;
+        }
+
+        self.loader.watch = watch.unwrap_or(self.loader.watch);
+        self.loader.hash = hash.unwrap_or(self.loader.hash);
+
+        match (&mut self.geonames, geonames, geonames_hierarchy) {
+            (Some(config), dump, hierarchy) => {
+                set(&mut config.dump, dump);
+                set(&mut config.hierarchy, hierarchy);
+            }
+            // Without a config section both dumps have to be given
+            (None, Some(dump), Some(hierarchy)) => {
+                self.geonames = Some(GeoNamesConfig {
+                    dump,
+                    hierarchy,
+                    alternate_names: None,
+                    countries: None,
+                    admin_codes: Vec::new(),
+                    features: Vec::new(),
+                    max_distance: None,
+                });
+            }
+            (None, Some(_), None) | (None, None, Some(_)) => {
+                return Err(
+                    "Without a [geonames] section, both the GeoNames dump and its \
+                    hierarchy have to be given"
+                        .into(),
+                );
+            }
+            (None, None, None) => {}
+        }
+
+        Ok(())
+    }
+
+    /// Creates a handle with all configured storage roots, creating the primary one if needed
+    pub fn handle(&self, db: Database) -> Result<Handle, Box<dyn Error>> {
+        fs::create_dir_all(&self.storage)?;
+
+        let mut handle = Handle::new(db, self.storage.clone());
+        for (name, path) in &self.roots {
+            handle.add_root(StorageRoot::new(name, path));
+        }
+
+        Ok(handle)
+    }
+
+    /// Instantiates the enabled extractors
+    pub fn extractors(&self) -> Result<Vec<Box<dyn Extractor>>, Box<dyn Error>> {
+        self.extractors
+            .iter()
+            .map(|name| self.extractor(name))
+            .collect()
+    }
+
+    pub fn extractor(&self, name: &str) -> Result<Box<dyn Extractor>, Box<dyn Error>> {
+        Ok(match name {
+            "loader" => Box::new(
+                BlobLoader::new()
+                    .with_watching(self.loader.watch)
+                    .with_hashing(self.loader.hash),
+            ),
+            "mime" => Box::new(MimeInfer::new()),
+            "exif" => Box::new(ExifExtractor),
+            "video" => Box::new(VideoExtractor),
+            "xmp" => Box::new(XmpExtractor),
+            "thumbnail" => Box::new(ThumbnailExtractor::new()),
+            "phash" => Box::new(PerceptualHasher::default()),
+            "similarity" => Box::new(SimilarityLinker::new(HashAlgorithm::Difference)),
+            "geonames" => Box::new(self.load_geonames()?),
+            "log" => Box::new(Logger),
+            _ => return Err(format!("Unknown extractor {name}").into()),
+        })
+    }
+
+    fn load_geonames(&self) -> Result<GeoNames, Box<dyn Error>> {
+        let config = self
+            .geonames
+            .as_ref()
+            .ok_or("The geonames extractor requires a [geonames] section")?;
+
+        let dumps = GeoNamesDumps {
+            geonames: config.dump.clone(),
+            hierarchy: config.hierarchy.clone(),
+            countries: config.countries.clone(),
+            admin_codes: config.admin_codes.clone(),
+            alternate_names: config.alternate_names.clone(),
+            features: config.features.clone(),
+        };
+
+        let mut geonames = GeoNames::load_cached(&dumps, self.cache.join("geonames.bin"))?;
+
+        if let Some(max_distance) = config.max_distance {
+            geonames = geonames.with_max_distance(max_distance);
+        }
+
+        Ok(geonames)
+    }
+}
+
+impl Overrides {
+    /// Reads the `FIRN_*` environment variables through the given lookup
+    pub fn from_env(var: impl Fn(&str) -> Option<String>) -> Self {
+        let path = |name: &str| var(name).map(PathBuf::from);
+
+        Self {
+            config: path("FIRN_CONFIG"),
+            storage: path("FIRN_STORAGE"),
+            database: path("FIRN_DATABASE"),
+            hierarchy: path("FIRN_HIERARCHY"),
+            cache: path("FIRN_CACHE"),
+            extractors: var("FIRN_EXTRACTORS").map(|list| split_list(&list)),
+            geonames: path("FIRN_GEONAMES"),
+            geonames_hierarchy: path("FIRN_GEONAMES_HIERARCHY"),
+            watch: var("FIRN_WATCH").map(|flag| is_enabled(&flag)),
+            hash: var("FIRN_HASH").map(|flag| is_enabled(&flag)),
+        }
+    }
+
+    /// Parses flags like `--storage <path>` or `--extractors=loader,mime`
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Self, Box<dyn Error>> {
+        let args = std::iter::once(String::from("firn")).chain(args);
+        Ok(Self::try_parse_from(args)?)
+    }
+
+    /// Takes all settings present in `other`
+    pub fn merge(&mut self, other: Overrides) {
+        macro_rules! take {
+            ($($field:ident),*) => {
+                $(if other.$field.is_some() {
+                    self.$field = other.$field;
+                })*
+            };
+        }
+
+        take!(
+            config,
+            storage,
+            database,
+            hierarchy,
+            cache,
+            extractors,
+            geonames,
+            geonames_hierarchy,
+            watch,
+            hash
+        );
+    }
+}
+
+fn is_enabled(flag: &str) -> bool {
+    matches!(flag.trim(), "1" | "true" | "yes" | "on")
+}
+
+fn split_list(list: &str) -> Vec<String> {
+    list.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(Into::into)
+        .collect()
+}
+
+#[cfg(test)]
+mod does {
+    use super::*;
+
+    #[test]
+    fn resolve_paths_relative_to_config_file() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("firn.toml");
+        fs::write(
+            &path,
+            r#"
+            storage = "photos"
+            extractors = ["loader", "geonames"]
+
+            [roots]
+            archive = "/mnt/archive"
+
+            [loader]
+            watch = true
+
+            [geonames]
+            dump = "geonames/DE.txt"
+            hierarchy = "geonames/hierarchy.txt"
+            features = ["S", "T"]
+            "#,
+        )?;
+
+        let config = Config::from_file(&path)?;
+
+        assert_eq!(config.storage, dir.path().join("photos"));
+        assert_eq!(config.roots["archive"], PathBuf::from("/mnt/archive"));
+        assert_eq!(config.hierarchy, dir.path().join("data/hierarchy"));
+        assert_eq!(config.extractors, vec!["loader", "geonames"]);
+        assert_eq!(
+            config.loader,
+            LoaderConfig {
+                watch: true,
+                hash: false
+            }
+        );
+
+        let geonames = config.geonames.unwrap();
+        assert_eq!(geonames.dump, dir.path().join("geonames/DE.txt"));
+        assert_eq!(geonames.features, vec![FeatureClass::S, FeatureClass::T]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn prefer_arguments_over_environment_over_file() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("custom.toml");
+        fs::write(
+            &path,
+            "storage = \"/from/file\"\ndatabase = \"/from/file.db\"\n",
+        )?;
+
+        let mut overrides = Overrides::from_env(|name| match name {
+            "FIRN_CONFIG" => Some(path.to_string_lossy().into()),
+            "FIRN_DATABASE" => Some("/from/env.db".into()),
+            "FIRN_STORAGE" => Some("/from/env".into()),
+            "FIRN_EXTRACTORS" => Some("loader, mime".into()),
+            "FIRN_WATCH" => Some("1".into()),
+            "FIRN_HASH" => Some("1".into()),
+            _ => None,
+        });
+        overrides.merge(Overrides::from_args(
+            ["--storage", "/from/args", "--watch", "--hash=false"].map(Into::into),
+        )?);
+
+        let config = Config::load_with(&overrides)?;
+
+        assert_eq!(config.storage, PathBuf::from("/from/args"));
+        assert_eq!(config.database, PathBuf::from("/from/env.db"));
+        assert_eq!(config.extractors, vec!["loader", "mime"]);
+        assert!(config.loader.watch);
+        assert!(!config.loader.hash);
+
+        // Half a GeoNames config is an error rather than silently no config
+        overrides.merge(Overrides::from_args(["--geonames=DE.txt".into()])?);
+        assert!(Config::load_with(&overrides).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_unknown_settings() {
+        assert!(toml::from_str::<Config>("storge = \"typo\"").is_err());
+        assert!(Overrides::from_args(["--storge".into(), "typo".into()]).is_err());
+        assert!(Config::default().extractor("nonexistent").is_err());
+        assert!(Config::default().extractor("geonames").is_err());
+    }
+
+    #[test]
+    fn reject_colons_in_root_names() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let path = dir.path().join("firn.toml");
+        fs::write(&path, "[roots]\n\"nas:photos\" = \"/mnt/photos\"\n")?;
+
+        let overrides = Overrides {
+            config: Some(path),
+            ..Default::default()
+        };
+        assert!(Config::load_with(&overrides).is_err());
+
+        Ok(())
+    }
+}
@@ -1,6 +1,16 @@
 #![allow(dead_code)]
 
-use std::{fmt, sync::mpsc};
+use serde::{
+    de::{self, MapAccess, Visitor},
+    ser::SerializeMap,
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+use std::{
+    error::Error,
+    fmt,
+    io::{BufRead, Write},
+    sync::mpsc,
+};
 use triplet_tree::TripletTree;
 
 mod query;
@@ -8,10 +18,12 @@ mod triplet_tree;
 
 pub use query::*;
 
-#[derive(PartialEq, Eq, Hash, Clone)]
+#[derive(PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct Entity(pub String);
 
-#[derive(PartialEq, Eq, Hash, Clone)]
+#[derive(PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
 pub struct Attribute(pub String);
 
 pub type Data = String;
@@ -37,6 +49,91 @@ impl Value {
     }
 }
 
+/// Key marking references in serialized values, borrowed from JSON-LD
+const REFERENCE_KEY: &str = "@id";
+
+/// Data is serialized as plain strings and references as `{ "@id": "entity" }`
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Value::Data(data) => serializer.serialize_str(data),
+            Value::Reference(entity) => {
+                let mut map = serializer.serialize_map(Some(1))?;
+                map.serialize_entry(REFERENCE_KEY, &entity.0)?;
+                map.end()
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Value {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ValueVisitor;
+
+        impl<'de> Visitor<'de> for ValueVisitor {
+            type Value = Value;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "a string or a map with a single {REFERENCE_KEY:?} key")
+            }
+
+            fn visit_str<E: de::Error>(self, data: &str) -> Result<Value, E> {
+                Ok(Value::Data(data.to_owned()))
+            }
+
+            fn visit_string<E: de::Error>(self, data: String) -> Result<Value, E> {
+                Ok(Value::Data(data))
+            }
+
+            fn visit_map<M: MapAccess<'de>>(self, mut map: M) -> Result<Value, M::Error> {
+                let entity = match map.next_entry::<String, String>()? {
+                    Some((key, entity)) if key == REFERENCE_KEY => entity,
+                    Some((key, _)) => return Err(de::Error::unknown_field(&key, &[REFERENCE_KEY])),
+                    None => return Err(de::Error::missing_field(REFERENCE_KEY)),
+                };
+
+                if map.next_key::<String>()?.is_some() {
+                    return Err(de::Error::invalid_length(2, &self));
+                }
+
+                Ok(Value::Reference(Entity(entity)))
+            }
+        }
+
+        deserializer.deserialize_any(ValueVisitor)
+    }
+}
+
+/// Single entity, attribute, value triplet, e.g. for exchanging facts with other programs
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Fact {
+    pub entity: Entity,
+    pub attribute: Attribute,
+    pub value: Value,
+}
+
+/// Borrowed [`Fact`] to avoid cloning everything when exporting
+#[derive(Serialize)]
+struct FactRef<'a> {
+    entity: &'a Entity,
+    attribute: &'a Attribute,
+    value: &'a Value,
+}
+
+impl Fact {
+    pub fn new(
+        entity: impl Into<Entity>,
+        attribute: impl Into<Attribute>,
+        value: impl Into<Value>,
+    ) -> Self {
+        Self {
+            entity: entity.into(),
+            attribute: attribute.into(),
+            value: value.into(),
+        }
+    }
+}
+
 impl fmt::Debug for Value {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
@@ -128,6 +225,10 @@ impl Database {
         self.eav.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.eav.is_empty()
+    }
+
     pub fn insert(
         &mut self,
         entity: impl Into<Entity>,
@@ -147,6 +248,105 @@ impl Database {
         self.insert_into_indices(entity, attribute, value);
     }
 
+    /// Removes a single fact from the database. Unlike insertions, retractions are not
+    /// forwarded to the write log, so extractors will not be notified about them.
+    pub fn retract(&mut self, entity: &Entity, attribute: &Attribute, value: &Value) {
+        self.eav.remove_value(entity, attribute, value);
+        self.ave.remove_value(attribute, value, entity);
+        self.vae.remove_value(value, attribute, entity);
+    }
+
+    /// Removes all values of an attribute from an entity
+    pub fn retract_attribute(&mut self, entity: &Entity, attribute: &Attribute) {
+        let values = self.get(entity, attribute).cloned().collect::<Vec<_>>();
+
+        for value in values {
+            self.retract(entity, attribute, &value);
+        }
+    }
+
+    /// Removes every fact about an entity. Facts on other entities referencing it are retained.
+    pub fn retract_entity(&mut self, entity: &Entity) {
+        let facts = self
+            .eav
+            .get(entity)
+            .flat_map(|(attribute, values)| values.iter().map(|v| (attribute.clone(), v.clone())))
+            .collect::<Vec<_>>();
+
+        for (attribute, value) in facts {
+            self.retract(entity, &attribute, &value);
+        }
+    }
+
+    /// Writes all facts as newline delimited JSON, one [`Fact`] per line. Facts are sorted by
+    /// entity and attribute so dumps of the same database can be diffed.
+    pub fn export(&self, mut writer: impl Write) -> Result<usize, Box<dyn Error>> {
+        let mut keys: Vec<_> = self.eav.scan().collect();
+        keys.sort_by(|a, b| (&a.0 .0, &a.1 .0).cmp(&(&b.0 .0, &b.1 .0)));
+
+        let mut count = 0;
+        for (entity, attribute, values) in keys {
+            for value in values {
+                let fact = FactRef {
+                    entity,
+                    attribute,
+                    value,
+                };
+
+                serde_json::to_writer(&mut writer, &fact)?;
+                writer.write_all(b"\n")?;
+                count += 1;
+            }
+        }
+
+        writer.flush()?;
+        Ok(count)
+    }
+
+    /// Reads facts written by [`Database::export`], returning how many were added. Facts that
+    /// are already present are skipped. Imported facts bypass the write log, so extractors
+    /// will not process them again.
+    pub fn import(&mut self, reader: impl BufRead) -> Result<usize, Box<dyn Error>> {
+        self.import_where(reader, |_| true)
+    }
+
+    /// Like [`Database::import`], but only adds the facts matching the filter
+    pub fn import_where(
+        &mut self,
+        reader: impl BufRead,
+        filter: impl Fn(&Fact) -> bool,
+    ) -> Result<usize, Box<dyn Error>> {
+        let mut count = 0;
+
+        for (number, line) in reader.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+
+            let fact: Fact = serde_json::from_str(&line)
+                .map_err(|e| format!("Invalid fact on line {}: {e}", number + 1))?;
+
+            if !filter(&fact)
+                || self
+                    .get(&fact.entity, &fact.attribute)
+                    .any(|v| v == &fact.value)
+            {
+                continue;
+            }
+
+            self.eav.append(
+                fact.entity.clone(),
+                fact.attribute.clone(),
+                fact.value.clone(),
+            );
+            self.insert_into_indices(fact.entity, fact.attribute, fact.value);
+            count += 1;
+        }
+
+        Ok(count)
+    }
+
     fn insert_into_indices(&mut self, entity: Entity, attribute: Attribute, value: Value) {
         // self.aev.append(&attribute, &entity, &value)?;
         self.ave
@@ -180,3 +380,120 @@ impl Database {
         //     .flat_map(move |mut set| set.take(&value))
     }
 }
+
+#[cfg(test)]
+mod does {
+    use super::*;
+    use crate::query;
+    use serde_json::json;
+
+    #[test]
+    fn serialize_references_apart_from_data() -> Result<(), serde_json::Error> {
+        let facts = vec![
+            Fact::new("a.jpg", "image/camera", Entity::from("Pixel 7")),
+            Fact::new("a.jpg", "blob/size", 1024),
+        ];
+
+        let serialized = serde_json::to_value(&facts)?;
+        assert_eq!(
+            serialized,
+            json!([
+                { "entity": "a.jpg", "attribute": "image/camera", "value": { "@id": "Pixel 7" } },
+                { "entity": "a.jpg", "attribute": "blob/size", "value": "1024" },
+            ])
+        );
+
+        assert_eq!(serde_json::from_value::<Vec<Fact>>(serialized)?, facts);
+        Ok(())
+    }
+
+    #[test]
+    fn export_and_import_facts() -> Result<(), Box<dyn Error>> {
+        let (mut db, _) = Database::new();
+        db.insert("b.jpg", "blob/size", 2048);
+        db.insert("a.jpg", "image/camera", Entity::from("Pixel 7"));
+        db.insert("a.jpg", "blob/name", "IMG_1.jpg");
+        db.insert("a.jpg", "blob/name", "IMG_1 \"copy\".jpg");
+
+        let mut dump = Vec::new();
+        assert_eq!(db.export(&mut dump)?, 4);
+        let dump = String::from_utf8(dump)?;
+        assert_eq!(
+            dump,
+            concat!(
+                r#"{"entity":"a.jpg","attribute":"blob/name","value":"IMG_1.jpg"}"#,
+                "\n",
+                r#"{"entity":"a.jpg","attribute":"blob/name","value":"IMG_1 \"copy\".jpg"}"#,
+                "\n",
+                r#"{"entity":"a.jpg","attribute":"image/camera","value":{"@id":"Pixel 7"}}"#,
+                "\n",
+                r#"{"entity":"b.jpg","attribute":"blob/size","value":"2048"}"#,
+                "\n",
+            )
+        );
+
+        let (mut restored, write_log) = Database::new();
+        assert_eq!(restored.import(dump.as_bytes())?, 4);
+        assert_eq!(restored.import(dump.as_bytes())?, 0);
+        assert!(write_log.try_recv().is_err());
+
+        let mut again = Vec::new();
+        restored.export(&mut again)?;
+        assert_eq!(String::from_utf8(again)?, dump);
+        assert_eq!(
+            restored
+                .vae
+                .values(&Entity::from("Pixel 7").into(), &"image/camera".into())
+                .next(),
+            Some(&Entity::from("a.jpg"))
+        );
+
+        let error = restored
+            .import("\n{\"entity\":\"a\"}\n".as_bytes())
+            .unwrap_err();
+        assert!(error.to_string().starts_with("Invalid fact on line 2"));
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_malformed_values() {
+        let parse = |value| serde_json::from_value::<Value>(value).is_err();
+
+        assert!(parse(json!({ "id": "a.jpg" })));
+        assert!(parse(json!({ "@id": "a.jpg", "label": "A" })));
+        assert!(parse(json!({})));
+        assert!(parse(json!(42)));
+    }
+
+    #[test]
+    fn serialize_query_results() -> Result<(), serde_json::Error> {
+        let (mut db, _) = Database::new();
+        db.insert("a.jpg", "image/camera", Entity::from("Pixel 7"));
+        db.insert("Pixel 7", "device/manufacturer", "Google");
+
+        query!(db where (#image, #camera, ?make) match [
+            { #image, :"image/camera", #camera },
+            { #camera, :"device/manufacturer", ?make }
+        ] => results);
+
+        let serialized = serde_json::to_value(&results)?;
+        assert_eq!(
+            serialized,
+            json!([{
+                "entities": { "camera": "Pixel 7", "image": "a.jpg" },
+                "attributes": {},
+                "values": {
+                    "camera": { "@id": "Pixel 7" },
+                    "image": { "@id": "a.jpg" },
+                    "make": "Google"
+                }
+            }])
+        );
+
+        let restored: Vec<VariableSet> = serde_json::from_value(serialized)?;
+        assert_eq!(restored[0].get(&image), Some(&Entity::from("a.jpg")));
+        assert_eq!(restored[0].get(&make), Some(&Value::from("Google")));
+        Ok(())
+    }
+}
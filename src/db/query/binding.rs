@@ -1,22 +1,72 @@
 use super::super::{Attribute, Entity, Value};
-use std::{collections::HashMap, fmt, marker::PhantomData, sync::atomic::AtomicU64};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fmt,
+    marker::PhantomData,
+    sync::atomic::AtomicU64,
+};
 
 static COUNTER: AtomicU64 = AtomicU64::new(0);
 
 #[derive(PartialEq, Eq, Hash, Clone)]
 pub struct Variable<T>(String, PhantomData<T>);
 
-#[derive(Default, Clone, Debug)]
+/// Bindings of a single query result, serialized as maps from variable names to bindings,
+/// sorted by name to keep the output stable
+#[derive(Default, Clone, Debug, Serialize, Deserialize)]
 pub struct VariableSet {
+    #[serde(rename = "entities", serialize_with = "sorted")]
     entity: HashMap<Variable<Entity>, Entity>,
+    #[serde(rename = "attributes", serialize_with = "sorted")]
     attribute: HashMap<Variable<Attribute>, Attribute>,
+    #[serde(rename = "values", serialize_with = "sorted")]
     value: HashMap<Variable<Value>, Value>,
 }
 
+fn sorted<S: Serializer, T, B: Serialize>(
+    bindings: &HashMap<Variable<T>, B>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    let bindings: BTreeMap<_, _> = bindings.iter().map(|(k, v)| (k.name(), v)).collect();
+    bindings.serialize(serializer)
+}
+
 impl<T> Variable<T> {
     pub fn new(name: impl Into<String>) -> Self {
         Self(name.into(), PhantomData)
     }
+
+    pub fn name(&self) -> &str {
+        &self.0
+    }
+}
+
+impl VariableSet {
+    pub fn entities(&self) -> &HashMap<Variable<Entity>, Entity> {
+        &self.entity
+    }
+
+    pub fn attributes(&self) -> &HashMap<Variable<Attribute>, Attribute> {
+        &self.attribute
+    }
+
+    /// Bindings of value variables, which include references bound to entity variables
+    pub fn values(&self) -> &HashMap<Variable<Value>, Value> {
+        &self.value
+    }
+}
+
+impl<T> Serialize for Variable<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.0)
+    }
+}
+
+impl<'de, T> Deserialize<'de> for Variable<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer).map(Variable::new)
+    }
 }
 
 impl From<Variable<Value>> for Variable<Entity> {
@@ -1,16 +1,18 @@
 use super::Database;
 
 mod binding;
+mod parse;
 mod rule;
 
 pub use binding::*;
+pub use parse::*;
 pub use rule::*;
 
 impl Database {
     #[must_use]
     pub fn query<'v>(&self, rules: &'v Vec<Rule<'v>>) -> Vec<VariableSet> {
         let mut valid_sets = Vec::new();
-        self.constrain_binding_sets(VariableSet::default(), &rules, &mut valid_sets);
+        self.constrain_binding_sets(VariableSet::default(), rules, &mut valid_sets);
         valid_sets
     }
 
@@ -0,0 +1,400 @@
+use super::{Rule, RuleVal, Variable, VariableSet, VariableSetExt};
+use crate::db::{Attribute, Database, Entity, Value};
+use std::{borrow::Cow, error::Error, iter::Peekable, str::CharIndices, str::FromStr};
+
+/// Query parsed at runtime, written the same way as with the `query!` macro:
+///
+/// ```text
+/// (#image, ?time) match [
+///     { #image, :"time/creation", ?time },
+///     { #image, :"image/camera", #"Pixel 7" }
+/// ]
+/// ```
+///
+/// Quoted terms are constants, bare ones are variables. The projection in front may be
+/// left out, in which case every variable is returned in order of appearance.
+pub struct Query {
+    variables: Vec<QueryVariable>,
+    rules: Vec<Rule<'static>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum QueryVariable {
+    Entity(Variable<Entity>),
+    Attribute(Variable<Attribute>),
+    Value(Variable<Value>),
+}
+
+impl QueryVariable {
+    pub fn name(&self) -> &str {
+        match self {
+            QueryVariable::Entity(variable) => variable.name(),
+            QueryVariable::Attribute(variable) => variable.name(),
+            QueryVariable::Value(variable) => variable.name(),
+        }
+    }
+
+    /// Prefix the variable is written with
+    fn sigil(&self) -> char {
+        match self {
+            QueryVariable::Entity(_) => '#',
+            QueryVariable::Attribute(_) => ':',
+            QueryVariable::Value(_) => '?',
+        }
+    }
+
+    /// Binding of the variable, entities are returned as references and attributes as data
+    pub fn get(&self, set: &VariableSet) -> Option<Value> {
+        match self {
+            QueryVariable::Entity(variable) => set.get(variable).map(Value::from),
+            QueryVariable::Attribute(variable) => set.get(variable).map(|a| Value::from(&a.0)),
+            QueryVariable::Value(variable) => set.get(variable).cloned(),
+        }
+    }
+}
+
+impl Query {
+    pub fn parse(input: &str) -> Result<Self, Box<dyn Error>> {
+        Parser {
+            input,
+            chars: input.char_indices().peekable(),
+        }
+        .query()
+    }
+
+    pub fn variables(&self) -> &[QueryVariable] {
+        &self.variables
+    }
+
+    /// Runs the query, returning the bindings of the projected variables for every match
+    pub fn run(&self, db: &Database) -> Vec<Vec<Option<Value>>> {
+        db.query(&self.rules)
+            .iter()
+            .map(|set| self.variables.iter().map(|v| v.get(set)).collect())
+            .collect()
+    }
+}
+
+impl FromStr for Query {
+    type Err = Box<dyn Error>;
+
+    fn from_str(input: &str) -> Result<Self, Self::Err> {
+        Self::parse(input)
+    }
+}
+
+/// Single `#`, `:` or `?` prefixed term of a rule
+enum Term {
+    Variable(char, String),
+    Constant(char, String),
+}
+
+struct Parser<'i> {
+    input: &'i str,
+    chars: Peekable<CharIndices<'i>>,
+}
+
+impl Parser<'_> {
+    fn query(mut self) -> Result<Query, Box<dyn Error>> {
+        let projection = match self.peek() {
+            Some('(') => {
+                self.expect('(')?;
+                let mut terms = vec![self.term()?];
+                while self.eat(',') {
+                    terms.push(self.term()?);
+                }
+                self.expect(')')?;
+                self.keyword("match")?;
+                Some(terms)
+            }
+            _ => None,
+        };
+
+        let mut variables = Vec::new();
+        let mut rules = Vec::new();
+
+        self.expect('[')?;
+        loop {
+            self.expect('{')?;
+            let entity = self.term()?;
+            self.expect(',')?;
+            let attribute = self.term()?;
+            self.expect(',')?;
+            let value = self.term()?;
+            self.expect('}')?;
+
+            for term in [&entity, &attribute, &value] {
+                if let Term::Variable(sigil, name) = term {
+                    if !variables.iter().any(|v: &QueryVariable| v.name() == name) {
+                        variables.push(variable(*sigil, name));
+                    }
+                }
+            }
+
+            rules.push(rule(entity, attribute, value)?);
+
+            if !self.eat(',') || self.peek() == Some(']') {
+                break;
+            }
+        }
+        self.expect(']')?;
+
+        if let Some((position, c)) = self.next() {
+            return Err(format!("Unexpected '{c}' at position {position}").into());
+        }
+
+        if let Some(projection) = projection {
+            variables = projection
+                .into_iter()
+                .map(|term| match term {
+                    Term::Variable(sigil, name) => {
+                        match variables.iter().find(|v| v.name() == name) {
+                            Some(used) if used.sigil() == sigil => Ok(used.clone()),
+                            // Selecting `?camera` when rules bind `#camera` would never match
+                            Some(used) => Err(format!(
+                                "Variable {sigil}{name} is used as {}{name} in the rules",
+                                used.sigil()
+                            )),
+                            None => Err(format!("Variable {sigil}{name} is not used in any rule")),
+                        }
+                    }
+                    Term::Constant(sigil, name) => {
+                        Err(format!("Cannot select constant {sigil}\"{name}\""))
+                    }
+                })
+                .collect::<Result<_, _>>()?;
+        }
+
+        Ok(Query { variables, rules })
+    }
+
+    fn term(&mut self) -> Result<Term, Box<dyn Error>> {
+        let sigil = match self.next() {
+            Some((_, c @ ('#' | ':' | '?'))) => c,
+            Some((position, c)) => {
+                return Err(
+                    format!("Expected '#', ':' or '?' at position {position}, found '{c}'").into(),
+                )
+            }
+            None => return Err("Unexpected end of query, expected a term".into()),
+        };
+
+        // No whitespace between the sigil and the name
+        match self.chars.peek() {
+            Some((_, '"')) => Ok(Term::Constant(sigil, self.string()?)),
+            _ => Ok(Term::Variable(sigil, self.identifier()?)),
+        }
+    }
+
+    fn string(&mut self) -> Result<String, Box<dyn Error>> {
+        let (start, _) = self.chars.next().ok_or("Unexpected end of query")?;
+        let mut string = String::new();
+
+        loop {
+            match self.chars.next() {
+                Some((_, '"')) => return Ok(string),
+                Some((_, '\\')) => match self.chars.next() {
+                    Some((_, c)) => string.push(c),
+                    None => break,
+                },
+                Some((_, c)) => string.push(c),
+                None => break,
+            }
+        }
+
+        Err(format!("Unterminated string starting at position {start}").into())
+    }
+
+    fn identifier(&mut self) -> Result<String, Box<dyn Error>> {
+        let mut identifier = String::new();
+
+        while let Some((_, c)) = self
+            .chars
+            .next_if(|(_, c)| c.is_alphanumeric() || *c == '_')
+        {
+            identifier.push(c);
+        }
+
+        if identifier.is_empty() {
+            let position = self.position();
+            return Err(format!("Expected a name or string at position {position}").into());
+        }
+
+        Ok(identifier)
+    }
+
+    fn keyword(&mut self, keyword: &str) -> Result<(), Box<dyn Error>> {
+        self.skip_whitespace();
+        let position = self.position();
+
+        if self.identifier().ok().as_deref() != Some(keyword) {
+            return Err(format!("Expected '{keyword}' at position {position}").into());
+        }
+
+        Ok(())
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), Box<dyn Error>> {
+        match self.next() {
+            Some((_, c)) if c == expected => Ok(()),
+            Some((position, c)) => {
+                Err(format!("Expected '{expected}' at position {position}, found '{c}'").into())
+            }
+            None => Err(format!("Unexpected end of query, expected '{expected}'").into()),
+        }
+    }
+
+    /// Consumes the next character if it matches
+    fn eat(&mut self, expected: char) -> bool {
+        self.skip_whitespace();
+        self.chars.next_if(|(_, c)| *c == expected).is_some()
+    }
+
+    fn peek(&mut self) -> Option<char> {
+        self.skip_whitespace();
+        self.chars.peek().map(|(_, c)| *c)
+    }
+
+    /// Next character that isn't whitespace
+    fn next(&mut self) -> Option<(usize, char)> {
+        self.skip_whitespace();
+        self.chars.next()
+    }
+
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|(_, c)| c.is_whitespace()).is_some() {}
+    }
+
+    fn position(&mut self) -> usize {
+        self.chars
+            .peek()
+            .map(|(position, _)| *position)
+            .unwrap_or(self.input.len())
+    }
+}
+
+fn variable(sigil: char, name: &str) -> QueryVariable {
+    match sigil {
+        '#' => QueryVariable::Entity(Variable::new(name)),
+        ':' => QueryVariable::Attribute(Variable::new(name)),
+        _ => QueryVariable::Value(Variable::new(name)),
+    }
+}
+
+fn rule(entity: Term, attribute: Term, value: Term) -> Result<Rule<'static>, Box<dyn Error>> {
+    let entity = match entity {
+        Term::Variable('#', name) => RuleVal::Variable(Cow::Owned(Variable::new(name))),
+        Term::Constant('#', id) => RuleVal::Constant(Entity::from(id)),
+        _ => return Err("The first term of a rule has to be an entity (#)".into()),
+    };
+
+    let attribute = match attribute {
+        Term::Variable(':', name) => RuleVal::Variable(Cow::Owned(Variable::new(name))),
+        Term::Constant(':', name) => RuleVal::Constant(Attribute::from(name)),
+        _ => return Err("The second term of a rule has to be an attribute (:)".into()),
+    };
+
+    let value = match value {
+        // Entity variables in value position only match references
+        Term::Variable('#' | '?', name) => RuleVal::Variable(Cow::Owned(Variable::new(name))),
+        Term::Constant('#', id) => RuleVal::Constant(Value::Reference(Entity::from(id))),
+        Term::Constant('?', data) => RuleVal::Constant(Value::Data(data)),
+        _ => return Err("The third term of a rule has to be a value (?) or entity (#)".into()),
+    };
+
+    Ok(Rule::new(entity, attribute, value))
+}
+
+#[cfg(test)]
+mod does {
+    use super::*;
+
+    fn database() -> Database {
+        let (mut db, _) = Database::new();
+        db.insert("a.jpg", "time/creation", "2023-01-14T18:30:05Z");
+        db.insert("a.jpg", "image/camera", Entity::from("Pixel 7"));
+        db.insert("b.jpg", "time/creation", "2021-06-01T12:00:00Z");
+        db.insert("b.jpg", "image/camera", Entity::from("X100V"));
+        db.insert("Pixel 7", "device/manufacturer", "Google");
+        db
+    }
+
+    #[test]
+    fn run_parsed_queries() -> Result<(), Box<dyn Error>> {
+        let db = database();
+
+        let query = Query::parse(
+            r#"(#image, ?make) match [
+                { #image, :"image/camera", #camera },
+                { #camera, :"device/manufacturer", ?make },
+            ]"#,
+        )?;
+
+        assert_eq!(query.variables().len(), 2);
+        assert_eq!(
+            query.run(&db),
+            vec![vec![
+                Some(Value::Reference("a.jpg".into())),
+                Some("Google".into())
+            ]]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn select_all_variables_without_projection() -> Result<(), Box<dyn Error>> {
+        let db = database();
+
+        let query: Query = r#"[{ #"b.jpg", :attribute, ?value }]"#.parse()?;
+        let names: Vec<_> = query.variables().iter().map(|v| v.name()).collect();
+        assert_eq!(names, vec!["attribute", "value"]);
+
+        let mut rows = query.run(&db);
+        rows.sort_by_key(|row| format!("{row:?}"));
+        assert_eq!(
+            rows,
+            vec![
+                vec![
+                    Some("image/camera".into()),
+                    Some(Value::Reference("X100V".into()))
+                ],
+                vec![
+                    Some("time/creation".into()),
+                    Some("2021-06-01T12:00:00Z".into())
+                ],
+            ]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn match_attributes_between_constants() -> Result<(), Box<dyn Error>> {
+        let db = database();
+
+        let query = Query::parse(r#"[{ #"a.jpg", :attribute, #"Pixel 7" }]"#)?;
+        assert_eq!(query.run(&db), vec![vec![Some("image/camera".into())]]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_malformed_queries() {
+        let error = |input: &str| Query::parse(input).err().map(|e| e.to_string());
+
+        assert_eq!(
+            error(r#"[{ #e, :"a" ?v }]"#).as_deref(),
+            Some("Expected ',' at position 12, found '?'")
+        );
+        assert!(error(r#"(?x) match [{ #e, :"a", ?v }]"#).is_some());
+        assert_eq!(
+            error(r#"(?camera) match [{ #image, :"image/camera", #camera }]"#).as_deref(),
+            Some("Variable ?camera is used as #camera in the rules")
+        );
+        assert!(error(r#"[{ ?e, :"a", ?v }]"#).is_some());
+        assert!(error(r#"[{ #e, :"a", ?"unterminated }]"#).is_some());
+        assert!(error(r#"[{ #e, :"a", ?v }] trailing"#).is_some());
+        assert!(error("").is_some());
+    }
+}
@@ -25,7 +25,7 @@ where
 {
     fn load(&self, set: &VariableSet) -> Self {
         if let RuleVal::Variable(var) = &self {
-            if let Some(value) = set.get(&var) {
+            if let Some(value) = set.get(var) {
                 return RuleVal::Constant(value.clone());
             }
         }
@@ -130,7 +130,12 @@ impl<'v> Rule<'v> {
             (Constant(entity), Constant(attribute), Variable(value)) => {
                 db.eav.constrain(set, Single(entity, attribute, &value))
             }
-            (Constant(_), Variable(_), Constant(_)) => unimplemented!(),
+            (Constant(entity), Variable(attribute), Constant(value)) => db
+                .eav
+                .get(&entity)
+                .filter(|(_, values)| values.contains(&value))
+                .map(|(a, _)| set.constrain(&attribute, a.clone()))
+                .collect(),
 
             // 2 variables
             (Variable(entity), Variable(attribute), Constant(value)) => {
@@ -169,14 +174,14 @@ where
             Single(a, b, c) => self
                 .values(&a, &b)
                 .cloned()
-                .map(|binding| set.constrain(&c, binding))
+                .map(|binding| set.constrain(c, binding))
                 .collect(),
 
             Double(a, b, c) => self
                 .get(&a)
                 .flat_map(|(v_b, v)| {
                     v.iter()
-                        .map(|v_c| set.constrain(&b, v_b.clone()).constrain(&c, v_c.clone()))
+                        .map(|v_c| set.constrain(b, v_b.clone()).constrain(c, v_c.clone()))
                 })
                 .collect(),
 
@@ -184,9 +189,9 @@ where
                 .scan()
                 .flat_map(|(v_a, v_b, v)| {
                     v.iter().map(|v_c| {
-                        set.constrain(&a, v_a.clone())
-                            .constrain(&b, v_b.clone())
-                            .constrain(&c, v_c.clone())
+                        set.constrain(a, v_a.clone())
+                            .constrain(b, v_b.clone())
+                            .constrain(c, v_c.clone())
                     })
                 })
                 .collect(),
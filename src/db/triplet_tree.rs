@@ -14,6 +14,10 @@ impl<K1: Hash + Eq + Clone, K2: Hash + Eq + Clone, V: Clone> TripletTree<K1, K2,
         self.0.len()
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
     /// Appends the value to the given key, retaining old values if present
     pub fn append(&mut self, key1: K1, key2: K2, value: V) {
         self.0
@@ -23,7 +27,7 @@ impl<K1: Hash + Eq + Clone, K2: Hash + Eq + Clone, V: Clone> TripletTree<K1, K2,
     }
 
     /// Retrieves all attribute + value combinations for a given first key
-    pub fn get<'k>(&'k self, key1: &'k K1) -> impl Iterator<Item = (&K2, &Vec<V>)> + 'k {
+    pub fn get<'k>(&'k self, key1: &'k K1) -> impl Iterator<Item = (&'k K2, &'k Vec<V>)> + 'k {
         self.0
             .iter()
             .filter(move |((k1, _), _)| key1 == k1)
@@ -43,6 +47,22 @@ impl<K1: Hash + Eq + Clone, K2: Hash + Eq + Clone, V: Clone> TripletTree<K1, K2,
         self.0.remove(&((*key1).clone(), (*key2).clone()));
     }
 
+    /// Deletes a single value for a given key, dropping the key once no values are left
+    pub fn remove_value(&mut self, key1: &K1, key2: &K2, value: &V)
+    where
+        V: PartialEq,
+    {
+        let key = ((*key1).clone(), (*key2).clone());
+
+        if let Some(set) = self.0.get_mut(&key) {
+            set.retain(|v| v != value);
+
+            if set.is_empty() {
+                self.0.remove(&key);
+            }
+        }
+    }
+
     pub fn clear(&mut self) {
         self.0.clear()
     }
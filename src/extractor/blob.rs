@@ -1,27 +1,677 @@
-use super::Extractor;
-use crate::{db::Value, handle::Handle};
-use std::path::PathBuf;
+use super::{Extractor, Failure, SimilarityLinker, XmpExtractor};
+use crate::{
+    db::{Entity, Rule, Value, Variable, VariableSetExt},
+    handle::{content_hash, modification_time, Handle, StorageRoot},
+    query,
+};
+use notify::{RecommendedWatcher, RecursiveMode, Watcher};
+use std::{
+    collections::{HashMap, HashSet},
+    error::Error,
+    ffi::OsStr,
+    fs::{File, Metadata},
+    io::BufReader,
+    path::{Component, Path, PathBuf},
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+use walkdir::WalkDir;
 
-pub struct BlobLoader(pub PathBuf);
+/// Time a file has to remain untouched before changes to it are picked up by the watcher,
+/// prevents half-written files from being passed on to other extractors.
+const SETTLE_TIME: Duration = Duration::from_millis(250);
 
-impl Extractor for BlobLoader {
-    fn init(&mut self, handle: &mut Handle) -> Result<(), Box<dyn std::error::Error>> {
-        let mut i = 0;
-        for entry in std::fs::read_dir(&self.0)? {
+/// How often loading a changed path is attempted before it is recorded as a failure
+const MAX_LOAD_ATTEMPTS: u32 = 5;
+
+/// Location of a blob within one of the storage roots
+struct BlobPath {
+    entity: Entity,
+    root: String,
+    /// Path relative to the storage root, using `/` as a separator
+    path: String,
+    dir: Option<String>,
+    name: String,
+    absolute: PathBuf,
+}
+
+/// Emits `blob/*` facts for all files in the storage roots of a [`Handle`] and their
+/// subdirectories.
+///
+/// Blobs that are already known to the database are compared by size and modification time
+/// and only passed on to other extractors if they changed. Facts about files that no longer
+/// exist are retracted, unless their storage root is currently unavailable.
+///
+/// In watching mode, the roots are monitored after the initial scan and changes are
+/// emitted as they happen, keeping the pipeline alive indefinitely.
+pub struct BlobLoader {
+    /// Roots of the handle with canonicalized paths, refreshed on every scan
+    roots: Vec<StorageRoot>,
+    hashing: bool,
+    watching: bool,
+    settle_time: Duration,
+    watcher: Option<(
+        RecommendedWatcher,
+        mpsc::Receiver<notify::Result<notify::Event>>,
+    )>,
+    /// Changed paths with the time of the last change and the number of failed loads
+    pending: HashMap<PathBuf, (Instant, u32)>,
+}
+
+impl Default for BlobLoader {
+    fn default() -> Self {
+        Self {
+            roots: Vec::new(),
+            hashing: false,
+            watching: false,
+            settle_time: SETTLE_TIME,
+            watcher: None,
+            pending: HashMap::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BlobChange {
+    Added,
+    Modified,
+    Unchanged,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ScanSummary {
+    pub added: usize,
+    pub modified: usize,
+    pub removed: usize,
+    pub unchanged: usize,
+    /// Storage roots that could not be accessed
+    pub unavailable: Vec<String>,
+}
+
+impl BlobLoader {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Keeps watching the directory for changes after the initial scan
+    pub fn with_watching(mut self, watching: bool) -> Self {
+        self.watching = watching;
+        self
+    }
+
+    /// Overrides how long files have to remain untouched before changes are picked up
+    pub fn with_settle_time(mut self, settle_time: Duration) -> Self {
+        self.settle_time = settle_time;
+        self
+    }
+
+    /// Stores a `blob/hash` for every file and uses it to tell apart files whose contents
+    /// changed from ones that have merely been touched. Requires reading every new file once.
+    pub fn with_hashing(mut self, hashing: bool) -> Self {
+        self.hashing = hashing;
+        self
+    }
+
+    /// Recursively walks all storage roots and brings the `blob/*` facts up to date
+    pub fn scan(&mut self, handle: &mut Handle) -> Result<ScanSummary, Box<dyn Error>> {
+        let mut summary = ScanSummary::default();
+        let mut seen = HashSet::new();
+
+        summary.unavailable = self.refresh_roots(handle);
+
+        for root in self.roots.clone() {
+            if let Err(e) = self.load_tree(handle, &root.path, &mut summary, &mut seen) {
+                // Roots that vanish mid-scan (e.g. unmounted disks) keep their blobs
+                if !root.path.exists() {
+                    summary.unavailable.push(root.name);
+                } else {
+                    return Err(e);
+                }
+            }
+        }
+
+        query!(handle where (#blob, ?size) match [
+            { #blob, :"blob/size", ?size }
+        ] => blobs);
+
+        let primary = handle.primary_root().name.clone();
+
+        for entity in blobs.iter().filter_map(|set| set.get(&blob)) {
+            let root = handle
+                .known(entity, "blob/root")
+                .unwrap_or_else(|| primary.clone());
+
+            if !seen.contains(entity) && !summary.unavailable.contains(&root) {
+                Self::remove(handle, entity);
+                summary.removed += 1;
+            }
+        }
+
+        Ok(summary)
+    }
+
+    /// Copies the storage roots from the handle, returning the names of all roots which
+    /// could not be accessed. Paths are canonicalized so they match those of watch events.
+    fn refresh_roots(&mut self, handle: &Handle) -> Vec<String> {
+        let mut unavailable = Vec::new();
+
+        self.roots = handle
+            .roots()
+            .iter()
+            .filter_map(|root| match root.path.canonicalize() {
+                Ok(path) => Some(StorageRoot::new(&root.name, path)),
+                Err(_) => {
+                    unavailable.push(root.name.clone());
+                    None
+                }
+            })
+            .collect();
+
+        unavailable
+    }
+
+    /// Loads all files below a directory within the storage directory
+    fn load_tree(
+        &self,
+        handle: &mut Handle,
+        dir: &Path,
+        summary: &mut ScanSummary,
+        seen: &mut HashSet<Entity>,
+    ) -> Result<(), Box<dyn Error>> {
+        let walker = WalkDir::new(dir)
+            .into_iter()
+            .filter_entry(|entry| entry.depth() == 0 || !is_hidden(entry.file_name()));
+
+        for entry in walker {
             let file = entry?;
-            if file.file_type()?.is_file() {
-                if let Ok(name) = file.file_name().into_string() {
-                    let size = file.metadata()?.len();
-                    handle.insert(name, "blob/size", Value::Data(size.to_string()));
-                    i += 1;
+            if file.file_type().is_file() {
+                if let Some(blob) = self.locate(handle, file.path()) {
+                    match self.load(handle, &blob, &file.metadata()?)? {
+                        BlobChange::Added => summary.added += 1,
+                        BlobChange::Modified => summary.modified += 1,
+                        BlobChange::Unchanged => summary.unchanged += 1,
+                    }
+
+                    seen.insert(blob.entity);
                 }
             }
+        }
+
+        Ok(())
+    }
+
+    /// Maps a path within one of the storage roots to the blob stored there. Hidden files and
+    /// directories are skipped as they are used for partially ingested or derived blobs.
+    fn locate(&self, handle: &Handle, path: &Path) -> Option<BlobPath> {
+        let (root, relative) = self
+            .roots
+            .iter()
+            .find_map(|root| Some((root, path.strip_prefix(&root.path).ok()?)))?;
+
+        let components = relative
+            .components()
+            .map(|component| match component {
+                Component::Normal(name) if !is_hidden(name) => name.to_str(),
+                _ => None,
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        let (name, dir) = components.split_last()?;
+
+        Some(BlobPath {
+            entity: handle.blob_entity(&root.name, &components.join("/")),
+            root: root.name.clone(),
+            path: components.join("/"),
+            dir: (!dir.is_empty()).then(|| dir.join("/")),
+            name: name.to_string(),
+            absolute: path.to_owned(),
+        })
+    }
+
+    /// Starts monitoring all available storage roots, events are buffered until the next `poll`
+    pub fn watch(&mut self, handle: &Handle) -> Result<(), Box<dyn Error>> {
+        self.refresh_roots(handle);
+
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::recommended_watcher(tx)?;
+
+        for root in self.roots.iter() {
+            watcher.watch(&root.path, RecursiveMode::Recursive)?;
+        }
+
+        self.watcher = Some((watcher, rx));
+        Ok(())
+    }
+
+    /// Processes all paths which received events and have settled since. Paths that fail to
+    /// load, e.g. because they are still being written, are tried again later.
+    fn process_events(&mut self, handle: &mut Handle) -> Result<(), Box<dyn Error>> {
+        if let Some((_, events)) = &self.watcher {
+            for event in events.try_iter() {
+                let paths = match event {
+                    Ok(event) => event.paths,
+                    // Errors about specific paths are handled like changes to them
+                    Err(e) if !e.paths.is_empty() => e.paths,
+                    Err(e) => return Err(e.into()),
+                };
+
+                for path in paths {
+                    self.pending.insert(path, (Instant::now(), 0));
+                }
+            }
+        }
+
+        let settled = self
+            .pending
+            .iter()
+            .filter(|(_, (changed, _))| changed.elapsed() >= self.settle_time)
+            .map(|(path, (_, attempts))| (path.clone(), *attempts))
+            .collect::<Vec<_>>();
+
+        for (path, attempts) in settled {
+            self.pending.remove(&path);
+
+            let Some(blob) = self.locate(handle, &path) else {
+                continue;
+            };
+
+            if let Err(e) = self.load_path(handle, &blob) {
+                if attempts + 1 < MAX_LOAD_ATTEMPTS {
+                    self.pending.insert(path, (Instant::now(), attempts + 1));
+                } else {
+                    Failure::new(
+                        self.name(),
+                        Some(&blob.entity),
+                        None,
+                        e.as_ref(),
+                        attempts + 1,
+                    )
+                    .record(handle);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Brings the facts about a single changed path up to date
+    fn load_path(&self, handle: &mut Handle, blob: &BlobPath) -> Result<(), Box<dyn Error>> {
+        match std::fs::metadata(&blob.absolute) {
+            Ok(metadata) if metadata.is_file() => {
+                self.load(handle, blob, &metadata)?;
+            }
+            // Directories moved into the storage only emit a single event
+            Ok(metadata) if metadata.is_dir() => {
+                let mut summary = ScanSummary::default();
+                self.load_tree(handle, &blob.absolute, &mut summary, &mut HashSet::new())?;
+            }
+            Ok(_) => {}
+            Err(_) => Self::remove_tree(handle, blob),
+        }
+
+        Ok(())
+    }
+
+    /// Compares a file against the facts known about it and updates them if necessary
+    fn load(
+        &self,
+        handle: &mut Handle,
+        blob: &BlobPath,
+        metadata: &Metadata,
+    ) -> Result<BlobChange, Box<dyn Error>> {
+        let entity = &blob.entity;
+        let size = metadata.len().to_string();
+        let mtime = modification_time(metadata)?;
+
+        let known_size = handle.known(entity, "blob/size");
+        let known_mtime = handle.known(entity, "blob/mtime");
+
+        let change = match known_size {
+            None => BlobChange::Added,
+            Some(known_size) if known_size == size && known_mtime.as_ref() == Some(&mtime) => {
+                return Ok(BlobChange::Unchanged);
+            }
+            Some(_) => BlobChange::Modified,
+        };
+
+        let hash = match self.hashing {
+            true => Some(content_hash(BufReader::new(File::open(&blob.absolute)?))?),
+            false => None,
+        };
+
+        // Contents are still the same, so the derived facts remain valid
+        if hash.is_some() && hash == handle.known(entity, "blob/hash") {
+            handle.retract_attribute(entity, &"blob/mtime".into());
+            handle.insert(entity.clone(), "blob/mtime", mtime);
+            return Ok(BlobChange::Unchanged);
+        }
+
+        if change == BlobChange::Modified {
+            Self::remove(handle, entity);
+        }
+
+        handle.insert(entity.clone(), "blob/size", size);
+        handle.insert(entity.clone(), "blob/mtime", mtime);
+        handle.insert(entity.clone(), "blob/root", blob.root.as_str());
+        handle.insert(entity.clone(), "blob/path", blob.path.as_str());
+        // `blob/name` is reserved for the names blobs were uploaded under
+        handle.insert(entity.clone(), "blob/file_name", blob.name.as_str());
+
+        if let Some(dir) = &blob.dir {
+            handle.insert(entity.clone(), "blob/dir", dir.as_str());
+        }
+
+        if let Some(hash) = hash {
+            handle.insert(entity.clone(), "blob/hash", hash);
+        }
+
+        Ok(change)
+    }
+
+    /// Retracts a blob or, if it was a directory, all blobs contained within
+    fn remove_tree(handle: &mut Handle, blob: &BlobPath) {
+        let prefix = format!("{}/", blob.path);
+
+        query!(handle where (#entity, ?path) match [
+            { #entity, :"blob/path", ?path }
+        ] => blobs);
+
+        let contained = blobs
+            .iter()
+            .filter(|set| {
+                set.get(&path)
+                    .is_some_and(|p| p.data().starts_with(&prefix))
+            })
+            .filter_map(|set| set.get(&entity).cloned())
+            .filter(|entity| handle.known(entity, "blob/root").as_ref() == Some(&blob.root))
+            .collect::<Vec<_>>();
+
+        for entity in contained {
+            Self::remove(handle, &entity);
+        }
+
+        Self::remove(handle, &blob.entity);
+    }
+
+    /// Retracts all facts about a blob, including failures recorded while processing it and
+    /// entities it owns, like face regions or links to similar images. Blobs derived from it, like thumbnails, are deleted
+    /// as they would be outdated.
+    pub fn remove(handle: &mut Handle, entity: &Entity) {
+        query!(handle where (#failure) match [
+            { #failure, :"failure/entity", #entity.clone() }
+        ] => failures);
+
+        for failure in failures.iter().filter_map(|set| set.get(&failure)) {
+            handle.retract_entity(failure);
+        }
+
+        query!(handle where (#derived) match [
+            { #derived, :"blob/source", #entity.clone() }
+        ] => derived_blobs);
+
+        for derived in derived_blobs.iter().filter_map(|set| set.get(&derived)) {
+            // Derived blobs may have been cleaned up already, which is fine
+            if let Ok(path) = handle.blob_path(derived) {
+                std::fs::remove_file(path).ok();
+            }
 
-            // if i > 200 {
-            //     break;
-            // }
+            Self::remove(handle, derived);
         }
 
+        XmpExtractor::retract(handle, entity);
+        SimilarityLinker::unlink(handle, entity);
+        handle.retract_entity(entity);
+    }
+}
+
+fn is_hidden(name: &OsStr) -> bool {
+    name.as_encoded_bytes().starts_with(b".")
+}
+
+impl Extractor for BlobLoader {
+    fn name(&self) -> &str {
+        "loader"
+    }
+
+    fn init(&mut self, handle: &mut Handle) -> Result<(), Box<dyn std::error::Error>> {
+        // Start watching first so no changes slip through while scanning
+        if self.watching {
+            self.watch(handle)?;
+        }
+
+        self.scan(handle)?;
+        Ok(())
+    }
+
+    fn is_live(&self) -> bool {
+        self.watcher.is_some()
+    }
+
+    fn poll(&mut self, handle: &mut Handle) -> Result<(), Box<dyn std::error::Error>> {
+        self.process_events(handle)
+    }
+}
+
+#[cfg(test)]
+mod does {
+    use super::*;
+    use crate::db::{Attribute, Database};
+    use std::{fs, io::Read};
+
+    #[test]
+    fn only_emit_changes() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let (db, write_log) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+        let mut loader = BlobLoader::new().with_hashing(true);
+
+        fs::write(dir.path().join("a.txt"), "a")?;
+        fs::write(dir.path().join("b.txt"), "b")?;
+        let summary = loader.scan(&mut handle)?;
+        assert_eq!((summary.added, summary.unchanged), (2, 0));
+
+        handle.insert("a.txt", "type/mime", "text/plain");
+        while write_log.try_recv().is_ok() {}
+
+        let summary = loader.scan(&mut handle)?;
+        assert_eq!((summary.added, summary.unchanged), (0, 2));
+        assert!(write_log.try_recv().is_err());
+
+        fs::write(dir.path().join("a.txt"), "changed")?;
+        fs::remove_file(dir.path().join("b.txt"))?;
+        let summary = loader.scan(&mut handle)?;
+        assert_eq!((summary.modified, summary.removed), (1, 1));
+
+        let a = Entity::from("a.txt");
+        let size = Attribute::from("blob/size");
+        assert_eq!(handle.get(&a, &size).collect::<Vec<_>>(), [&"7".into()]);
+        assert!(handle.get(&a, &"type/mime".into()).next().is_none());
+        assert!(handle.get(&"b.txt".into(), &size).next().is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn walk_nested_directories() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let (db, _) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+
+        fs::create_dir_all(dir.path().join("2021/summer"))?;
+        fs::create_dir_all(dir.path().join(".derived"))?;
+        fs::write(dir.path().join("2021/summer/beach.jpg"), "beach")?;
+        fs::write(dir.path().join(".derived/thumbnail.jpg"), "hidden")?;
+
+        let summary = BlobLoader::new().scan(&mut handle)?;
+        assert_eq!(summary.added, 1);
+
+        let beach = Entity::from("2021/summer/beach.jpg");
+        let fact = |attribute: &str| handle.known(&beach, attribute);
+        assert_eq!(fact("blob/path").as_deref(), Some("2021/summer/beach.jpg"));
+        assert_eq!(fact("blob/dir").as_deref(), Some("2021/summer"));
+        assert_eq!(fact("blob/file_name").as_deref(), Some("beach.jpg"));
+        assert_eq!(fact("blob/name"), None);
+        assert!(fact("blob/mtime").is_some());
+
+        let mut contents = String::new();
+        handle.blob(&beach)?.read_to_string(&mut contents)?;
+        assert_eq!(contents, "beach");
+        assert!(handle
+            .blob(&"2021/../2021/summer/beach.jpg".into())
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn load_from_multiple_roots() -> Result<(), Box<dyn Error>> {
+        let primary = tempfile::tempdir()?;
+        let nas = tempfile::tempdir()?;
+        let (db, _) = Database::new();
+        let mut handle = Handle::new(db, primary.path().into());
+        handle.add_root(StorageRoot::new("nas", nas.path()));
+
+        fs::write(primary.path().join("a.jpg"), "primary")?;
+        fs::write(nas.path().join("a.jpg"), "nas")?;
+
+        let mut loader = BlobLoader::new();
+        assert_eq!(loader.scan(&mut handle)?.added, 2);
+
+        let nas_blob = Entity::from("nas:a.jpg");
+        assert_eq!(handle.known(&nas_blob, "blob/root").as_deref(), Some("nas"));
+
+        let mut contents = String::new();
+        handle.blob(&nas_blob)?.read_to_string(&mut contents)?;
+        assert_eq!(contents, "nas");
+
+        // Blobs on roots which went offline are retained
+        let nas_path = nas.path().to_owned();
+        nas.close()?;
+        let summary = loader.scan(&mut handle)?;
+        assert_eq!(summary.unavailable, ["nas"]);
+        assert_eq!(summary.removed, 0);
+        assert!(handle.blob_path(&nas_blob)?.starts_with(nas_path));
+
+        Ok(())
+    }
+
+    #[test]
+    fn pick_up_changes_while_watching() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let (db, _) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+        // Without settling, only the delivery of events by the OS is waited for
+        let mut loader = BlobLoader::new()
+            .with_watching(true)
+            .with_settle_time(Duration::ZERO);
+
+        fs::write(dir.path().join("a.txt"), "a")?;
+        loader.init(&mut handle)?;
+        assert!(loader.is_live());
+
+        let size = Attribute::from("blob/size");
+        let wait_for = |loader: &mut BlobLoader, handle: &mut Handle, entity, exists| {
+            let start = Instant::now();
+            while start.elapsed() < Duration::from_secs(30) {
+                loader.poll(handle).unwrap();
+                if handle.get(&Entity::from(entity), &size).next().is_some() == exists {
+                    return true;
+                }
+                std::thread::sleep(Duration::from_millis(50));
+            }
+            false
+        };
+
+        fs::write(dir.path().join("b.txt"), "b")?;
+        assert!(wait_for(&mut loader, &mut handle, "b.txt", true));
+
+        fs::remove_file(dir.path().join("a.txt"))?;
+        assert!(wait_for(&mut loader, &mut handle, "a.txt", false));
+
+        Ok(())
+    }
+
+    #[test]
+    fn drain_without_waiting_for_the_watcher() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let (db, write_log) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+        let mut extractors = crate::make_extractors![
+            BlobLoader::new().with_watching(true),
+            crate::extractor::MimeInfer::new()
+        ];
+
+        fs::write(dir.path().join("a.png"), b"\x89PNG\r\n\x1a\n")?;
+        crate::init_extractors(&mut handle, &mut extractors);
+        assert!(extractors[0].is_live());
+
+        crate::drain_write_log(
+            &write_log,
+            &mut handle,
+            &mut extractors,
+            &Default::default(),
+        )?;
+        assert_eq!(
+            handle.get(&"a.png".into(), &"type/mime".into()).next(),
+            Some(&"image/png".into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn delete_derived_blobs_with_their_source() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let (db, _) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+        let mut loader = BlobLoader::new();
+
+        fs::write(dir.path().join("a.jpg"), "a")?;
+        loader.scan(&mut handle)?;
+
+        let source = Entity::from("a.jpg");
+        let (derived, path) = handle.derived_blob(&source, "thumbnail", "jpg");
+        fs::create_dir_all(path.parent().unwrap())?;
+        fs::write(&path, "thumbnail")?;
+        handle.insert(derived.clone(), "blob/path", ".derived/thumbnail/a.jpg.jpg");
+        handle.insert(derived.clone(), "blob/source", source.clone());
+        handle.insert(source.clone(), "image/thumbnail", derived.clone());
+
+        fs::remove_file(dir.path().join("a.jpg"))?;
+        assert_eq!(loader.scan(&mut handle)?.removed, 1);
+
+        assert!(!path.exists());
+        assert_eq!(handle.eav.get(&derived).count(), 0);
+        assert_eq!(handle.eav.get(&source).count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn remove_regions_with_their_image() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let (db, _) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+        let mut loader = BlobLoader::new();
+
+        fs::write(dir.path().join("a.jpg"), "a")?;
+        loader.scan(&mut handle)?;
+
+        let image = Entity::from("a.jpg");
+        let region = Entity::from("region:a.jpg:0");
+        let person = Entity::from("person:Alice");
+        handle.insert(person.clone(), "text/label", "Alice");
+        handle.insert(region.clone(), "region/person", person.clone());
+        handle.insert(image.clone(), "image/region", region.clone());
+
+        fs::remove_file(dir.path().join("a.jpg"))?;
+        assert_eq!(loader.scan(&mut handle)?.removed, 1);
+
+        assert_eq!(handle.eav.get(&region).count(), 0);
+        assert_eq!(handle.eav.get(&image).count(), 0);
+        // People may appear on other images as well
+        assert_eq!(handle.eav.get(&person).count(), 1);
+
         Ok(())
     }
 }
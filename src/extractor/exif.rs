@@ -1,6 +1,10 @@
 use std::io::{BufRead, Read, Seek};
 
 use exif::{DateTime, Field, In, Tag, Value::*};
+use time::{
+    format_description::well_known::Rfc3339, Date, Month, OffsetDateTime, PrimitiveDateTime,
+    Time, UtcOffset,
+};
 
 use super::{Extractor, MimeInfer};
 use crate::{
@@ -14,11 +18,85 @@ pub struct ExifExtractor;
 pub struct ExifData {
     pub width: Option<u32>,
     pub height: Option<u32>,
+    /// Local time the image was taken at, including subseconds and offset if present
     pub timestamp: Option<DateTime>,
+    /// Time the GPS fix was taken at, always in UTC
+    pub gps_timestamp: Option<OffsetDateTime>,
     pub lat: Option<f64>,
     pub lng: Option<f64>,
     pub alt: Option<f64>,
     pub camera: Option<(String, String)>,
+    /// Focal length in millimeters
+    pub focal_length: Option<f64>,
+    /// Focal length equivalent for 35 mm film, in millimeters
+    pub focal_length_35mm: Option<u32>,
+    /// F-number, i.e. focal length divided by the aperture diameter
+    pub aperture: Option<f64>,
+    /// Exposure time in seconds
+    pub exposure_time: Option<f64>,
+    pub iso: Option<u32>,
+    /// Lens manufacturer (if known) and model
+    pub lens: Option<(Option<String>, String)>,
+    /// EXIF orientation between 1 and 8, 1 being upright
+    pub orientation: Option<u32>,
+    /// Whether the flash fired
+    pub flash: Option<bool>,
+}
+
+impl ExifData {
+    /// Wall clock time the image was taken at, without any timezone information
+    pub fn local_time(&self) -> Option<PrimitiveDateTime> {
+        let timestamp = self.timestamp.as_ref()?;
+        let date = Date::from_calendar_date(
+            timestamp.year.into(),
+            Month::try_from(timestamp.month).ok()?,
+            timestamp.day,
+        )
+        .ok()?;
+        let time = Time::from_hms_nano(
+            timestamp.hour,
+            timestamp.minute,
+            timestamp.second,
+            timestamp.nanosecond.unwrap_or(0),
+        )
+        .ok()?;
+
+        Some(PrimitiveDateTime::new(date, time))
+    }
+
+    /// Offset of the local time from UTC, either as stored by the camera or derived from the
+    /// GPS timestamp. The latter is only accurate to the quarter hour and assumes that the GPS
+    /// fix was taken around the same time as the image.
+    pub fn utc_offset(&self) -> Option<UtcOffset> {
+        if let Some(offset) = self.timestamp.as_ref().and_then(|t| t.offset) {
+            return UtcOffset::from_whole_seconds(i32::from(offset) * 60).ok();
+        }
+
+        let local = self.local_time()?.assume_utc();
+        let difference = (local - self.gps_timestamp?).whole_minutes();
+        let quarters = (difference as f64 / 15.0).round() as i32;
+
+        // Anything beyond the range of real world time zones means the clocks were off
+        if quarters.abs() > 14 * 4 {
+            return None;
+        }
+
+        UtcOffset::from_whole_seconds(quarters * 15 * 60).ok()
+    }
+
+    /// Timezone-aware creation time, if the offset from UTC is known
+    pub fn creation_time(&self) -> Option<OffsetDateTime> {
+        Some(self.local_time()?.assume_offset(self.utc_offset()?))
+    }
+
+    /// Creation time to store, treating the local time as UTC if the offset is unknown. The
+    /// flag is set when UTC has been assumed.
+    pub fn creation_time_or_utc(&self) -> Option<(OffsetDateTime, bool)> {
+        match self.creation_time() {
+            Some(creation) => Some((creation, false)),
+            None => Some((self.local_time()?.assume_utc(), true)),
+        }
+    }
 }
 
 impl ExifExtractor {
@@ -31,62 +109,132 @@ impl ExifExtractor {
 
         if let Some(width) = exif
             .get_field(Tag::PixelXDimension, In::PRIMARY)
-            .map(long_to_u32)
-            .flatten()
+            .and_then(long_to_u32)
         {
             data.width = Some(width);
         }
 
         if let Some(height) = exif
             .get_field(Tag::PixelYDimension, In::PRIMARY)
-            .map(long_to_u32)
-            .flatten()
+            .and_then(long_to_u32)
         {
             data.height = Some(height)
         }
 
-        if let Some(timestamp) = exif
+        if let Some(mut timestamp) = exif
             .get_field(Tag::DateTimeOriginal, In::PRIMARY)
-            .map(ascii_to_str)
-            .flatten()
-            .map(|string| DateTime::from_ascii(string.as_bytes()).ok())
-            .flatten()
+            .and_then(ascii_to_str)
+            .and_then(|string| DateTime::from_ascii(string.as_bytes()).ok())
         {
+            // Both are optional, a broken value is treated the same as a missing one
+            if let Some(subsec) = exif
+                .get_field(Tag::SubSecTimeOriginal, In::PRIMARY)
+                .and_then(ascii_to_str)
+            {
+                timestamp.parse_subsec(subsec.as_bytes()).ok();
+            }
+
+            if let Some(offset) = exif
+                .get_field(Tag::OffsetTimeOriginal, In::PRIMARY)
+                .and_then(ascii_to_str)
+            {
+                timestamp.parse_offset(offset.as_bytes()).ok();
+            }
+
             data.timestamp = Some(timestamp);
         }
 
-        // TODO Take GPS*Ref into account as the coordinates may be S/W!
+        if let (Some(date), Some(time)) = (
+            exif.get_field(Tag::GPSDateStamp, In::PRIMARY)
+                .and_then(ascii_to_str)
+                .and_then(parse_gps_date),
+            exif.get_field(Tag::GPSTimeStamp, In::PRIMARY)
+                .and_then(gps_time),
+        ) {
+            data.gps_timestamp = Some(PrimitiveDateTime::new(date, time).assume_utc());
+        }
+
+        // The coordinates themselves are unsigned, the hemisphere is stored separately
         if let (Some(lat), Some(lng)) = (
             exif.get_field(Tag::GPSLatitude, In::PRIMARY)
-                .map(coord_to_decimal_degree)
-                .flatten(),
+                .and_then(coord_to_decimal_degree),
             exif.get_field(Tag::GPSLongitude, In::PRIMARY)
-                .map(coord_to_decimal_degree)
-                .flatten(),
+                .and_then(coord_to_decimal_degree),
         ) {
-            data.lat = Some(lat);
-            data.lng = Some(lng);
+            let lat_ref = exif
+                .get_field(Tag::GPSLatitudeRef, In::PRIMARY)
+                .and_then(ascii_to_str);
+            let lng_ref = exif
+                .get_field(Tag::GPSLongitudeRef, In::PRIMARY)
+                .and_then(ascii_to_str);
+
+            data.lat = Some(lat * hemisphere_sign(lat_ref, "S"));
+            data.lng = Some(lng * hemisphere_sign(lng_ref, "W"));
         }
 
         if let Some(alt) = exif
             .get_field(Tag::GPSAltitude, In::PRIMARY)
-            .map(rational_to_f64)
-            .flatten()
+            .and_then(rational_to_f64)
         {
-            data.alt = Some(alt);
+            // A reference of 1 means the altitude is below sea level
+            let below_sea_level = exif
+                .get_field(Tag::GPSAltitudeRef, In::PRIMARY)
+                .and_then(|field| field.value.get_uint(0))
+                == Some(1);
+
+            data.alt = Some(if below_sea_level { -alt } else { alt });
         }
 
         if let (Some(make), Some(model)) = (
             exif.get_field(Tag::Make, In::PRIMARY)
-                .map(ascii_to_str)
-                .flatten(),
+                .and_then(ascii_to_str),
             exif.get_field(Tag::Model, In::PRIMARY)
-                .map(ascii_to_str)
-                .flatten(),
+                .and_then(ascii_to_str),
         ) {
             data.camera = Some((make.to_owned(), model.to_owned()));
         }
 
+        data.focal_length = exif
+            .get_field(Tag::FocalLength, In::PRIMARY)
+            .and_then(rational_to_f64);
+        data.focal_length_35mm = exif
+            .get_field(Tag::FocalLengthIn35mmFilm, In::PRIMARY)
+            .and_then(uint_to_u32);
+        data.aperture = exif
+            .get_field(Tag::FNumber, In::PRIMARY)
+            .and_then(rational_to_f64);
+        data.exposure_time = exif
+            .get_field(Tag::ExposureTime, In::PRIMARY)
+            .and_then(rational_to_f64);
+        data.iso = exif
+            .get_field(Tag::PhotographicSensitivity, In::PRIMARY)
+            .and_then(uint_to_u32);
+        data.orientation = exif
+            .get_field(Tag::Orientation, In::PRIMARY)
+            .and_then(uint_to_u32)
+            .filter(|orientation| (1..=8).contains(orientation));
+
+        // The lowest bit tells whether the flash fired, the others describe its mode
+        data.flash = exif
+            .get_field(Tag::Flash, In::PRIMARY)
+            .and_then(uint_to_u32)
+            .map(|flash| flash & 1 == 1);
+
+        if let Some(model) = exif
+            .get_field(Tag::LensModel, In::PRIMARY)
+            .and_then(ascii_to_str)
+            .map(str::trim)
+            .filter(|model| !model.is_empty())
+        {
+            let make = exif
+                .get_field(Tag::LensMake, In::PRIMARY)
+                .and_then(ascii_to_str)
+                .map(str::trim)
+                .filter(|make| !make.is_empty());
+
+            data.lens = Some((make.map(str::to_owned), model.to_owned()));
+        }
+
         Ok(data)
     }
 
@@ -98,15 +246,6 @@ impl ExifExtractor {
         let mut blob = handle.blob(entity)?;
         let data = Self::extract(&mut blob)?;
 
-        // for f in exif.fields() {
-        //     println!(
-        //         "{} {} {}",
-        //         f.tag,
-        //         f.ifd_num,
-        //         f.display_value().with_unit(&exif)
-        //     );
-        // }
-
         if let Some(width) = data.width {
             handle.insert(entity.clone(), "image/width", width);
         }
@@ -115,23 +254,21 @@ impl ExifExtractor {
             handle.insert(entity.clone(), "image/height", height);
         }
 
-        if let Some(timestamp) = data.timestamp {
-            // TODO Use Tag::SubSecTimeOriginal for millis
-            // TODO Take Tag::OffsetOriginal into account for the time zone, yuck
-            // TODO Convert to RFC3339 or whatever it was
-            let formatted = format!(
-                "{:0>4}-{:0>2}-{:0>2}T{:0>2}:{:0>2}:{:0>2}.00Z",
-                timestamp.year,
-                timestamp.month,
-                timestamp.day,
-                timestamp.hour,
-                timestamp.minute,
-                timestamp.second
-            );
-            handle.insert(entity.clone(), "time/creation", formatted);
+        if let Some(local) = data.local_time() {
+            handle.insert(entity.clone(), "time/local", format_local_time(local)?);
+        }
+
+        // Without an offset only the wall clock time is known, so UTC is assumed to keep the
+        // image sortable. The GeoNames extractor replaces it once the timezone of the location
+        // is known.
+        if let Some((creation, assumed)) = data.creation_time_or_utc() {
+            handle.insert(entity.clone(), "time/creation", creation.format(&Rfc3339)?);
+
+            if assumed {
+                handle.insert(entity.clone(), "time/assumed_utc", "true");
+            }
         }
 
-        // TODO Take GPS*Ref into account as the coordinates may be S/W!
         if let (Some(lat), Some(lng)) = (data.lat, data.lng) {
             handle.insert(entity.clone(), "location/latitude", lat);
             handle.insert(entity.clone(), "location/longitude", lng);
@@ -142,21 +279,41 @@ impl ExifExtractor {
         }
 
         if let Some((make, model)) = data.camera {
-            let camera = Entity::from(model);
+            let camera = device(handle, model, Some(make));
+            handle.insert(entity.clone(), "image/camera", Value::Reference(camera));
+        }
 
-            if handle
-                .get(&camera, &"device/manufacturer".into())
-                .next()
-                .is_none()
-            {
-                handle.insert(
-                    camera.clone(),
-                    "device/manufacturer",
-                    uppercase_first_letter(&make),
-                );
-            }
+        if let Some((make, model)) = data.lens {
+            let lens = device(handle, model, make);
+            handle.insert(entity.clone(), "image/lens", Value::Reference(lens));
+        }
 
-            handle.insert(entity.clone(), "image/camera", Value::Reference(camera));
+        if let Some(focal_length) = data.focal_length {
+            handle.insert(entity.clone(), "image/focal_length", focal_length);
+        }
+
+        if let Some(focal_length) = data.focal_length_35mm {
+            handle.insert(entity.clone(), "image/focal_length_35mm", focal_length);
+        }
+
+        if let Some(aperture) = data.aperture {
+            handle.insert(entity.clone(), "image/aperture", aperture);
+        }
+
+        if let Some(exposure_time) = data.exposure_time {
+            handle.insert(entity.clone(), "image/exposure_time", exposure_time);
+        }
+
+        if let Some(iso) = data.iso {
+            handle.insert(entity.clone(), "image/iso", iso);
+        }
+
+        if let Some(orientation) = data.orientation {
+            handle.insert(entity.clone(), "image/orientation", orientation);
+        }
+
+        if let Some(flash) = data.flash {
+            handle.insert(entity.clone(), "image/flash", flash);
         }
 
         Ok(())
@@ -183,6 +340,13 @@ fn long_to_u32(field: &Field) -> Option<u32> {
     None
 }
 
+fn uint_to_u32(field: &Field) -> Option<u32> {
+    match &field.value {
+        Byte(_) | Short(_) | Long(_) => field.value.get_uint(0),
+        _ => None,
+    }
+}
+
 fn rational_to_f64(field: &Field) -> Option<f64> {
     if let Rational(r) = &field.value {
         if r.len() == 1 {
@@ -203,6 +367,74 @@ fn coord_to_decimal_degree(field: &Field) -> Option<f64> {
     None
 }
 
+/// Sign of a coordinate given its GPS reference, references other than the negative one
+/// (or none at all) are treated as north or east
+fn hemisphere_sign(reference: Option<&str>, negative: &str) -> f64 {
+    match reference {
+        Some(reference) if reference.trim().eq_ignore_ascii_case(negative) => -1.0,
+        _ => 1.0,
+    }
+}
+
+fn parse_gps_date(date: &str) -> Option<Date> {
+    let mut parts = date.trim_end_matches('\0').splitn(3, ':');
+    let year = parts.next()?.parse().ok()?;
+    let month = Month::try_from(parts.next()?.parse::<u8>().ok()?).ok()?;
+    let day = parts.next()?.parse().ok()?;
+
+    Date::from_calendar_date(year, month, day).ok()
+}
+
+fn gps_time(field: &Field) -> Option<Time> {
+    if let Rational(r) = &field.value {
+        if r.len() == 3 {
+            let seconds = r[2].to_f64();
+            return Time::from_hms_nano(
+                r[0].to_f64() as u8,
+                r[1].to_f64() as u8,
+                seconds.trunc() as u8,
+                (seconds.fract() * 1e9) as u32,
+            )
+            .ok();
+        }
+    }
+
+    None
+}
+
+/// Formats a wall clock time like an RFC3339 timestamp, just without the offset
+pub(super) fn format_local_time(time: PrimitiveDateTime) -> Result<String, time::error::Format> {
+    let formatted = time.assume_utc().format(&Rfc3339)?;
+    Ok(formatted.trim_end_matches('Z').to_owned())
+}
+
+/// Parses a time formatted by [`format_local_time`]
+pub(super) fn parse_local_time(time: &str) -> Result<PrimitiveDateTime, time::error::Parse> {
+    let parsed = OffsetDateTime::parse(&format!("{time}Z"), &Rfc3339)?;
+    Ok(PrimitiveDateTime::new(parsed.date(), parsed.time()))
+}
+
+/// Entity for a camera or lens, identified by its model name
+fn device(handle: &mut Handle, model: String, make: Option<String>) -> Entity {
+    let device = Entity::from(model);
+
+    if let Some(make) = make {
+        if handle
+            .get(&device, &"device/manufacturer".into())
+            .next()
+            .is_none()
+        {
+            handle.insert(
+                device.clone(),
+                "device/manufacturer",
+                uppercase_first_letter(&make),
+            );
+        }
+    }
+
+    device
+}
+
 fn uppercase_first_letter(s: &str) -> String {
     let lowercased = s.to_lowercase();
     let mut c = lowercased.chars();
@@ -213,6 +445,10 @@ fn uppercase_first_letter(s: &str) -> String {
 }
 
 impl Extractor for ExifExtractor {
+    fn name(&self) -> &str {
+        "exif"
+    }
+
     fn entry_added(
         &mut self,
         handle: &mut Handle,
@@ -232,3 +468,135 @@ impl Extractor for ExifExtractor {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod does {
+    use super::*;
+
+    fn timestamp(offset: Option<i16>) -> DateTime {
+        let mut timestamp = DateTime::from_ascii(b"2023:07:14 18:30:05").unwrap();
+        timestamp.parse_subsec(b"25").unwrap();
+        timestamp.offset = offset;
+        timestamp
+    }
+
+    #[test]
+    fn use_offset_and_subseconds() {
+        let data = ExifData {
+            timestamp: Some(timestamp(Some(-330))),
+            ..Default::default()
+        };
+
+        let creation = data.creation_time().unwrap().format(&Rfc3339).unwrap();
+        assert_eq!(creation, "2023-07-14T18:30:05.25-05:30");
+    }
+
+    #[test]
+    fn derive_offset_from_gps_time() {
+        let gps = Date::from_calendar_date(2023, Month::July, 14)
+            .unwrap()
+            .with_hms(16, 29, 41)
+            .unwrap()
+            .assume_utc();
+
+        let data = ExifData {
+            timestamp: Some(timestamp(None)),
+            gps_timestamp: Some(gps),
+            ..Default::default()
+        };
+        assert_eq!(data.utc_offset(), UtcOffset::from_hms(2, 0, 0).ok());
+
+        let unrelated = ExifData {
+            gps_timestamp: Some(gps - time::Duration::days(3)),
+            ..data
+        };
+        assert_eq!(unrelated.utc_offset(), None);
+        assert_eq!(unrelated.creation_time(), None);
+    }
+
+    #[test]
+    fn assume_utc_without_offset() {
+        let data = ExifData {
+            timestamp: Some(timestamp(None)),
+            ..Default::default()
+        };
+        assert_eq!(data.creation_time(), None);
+
+        let (creation, assumed) = data.creation_time_or_utc().unwrap();
+        assert_eq!(
+            creation.format(&Rfc3339).unwrap(),
+            "2023-07-14T18:30:05.25Z"
+        );
+        assert!(assumed);
+
+        let data = ExifData {
+            timestamp: Some(timestamp(Some(120))),
+            ..Default::default()
+        };
+        assert!(!data.creation_time_or_utc().unwrap().1);
+        assert_eq!(ExifData::default().creation_time_or_utc(), None);
+    }
+
+    #[test]
+    fn honor_gps_hemisphere_references() -> Result<(), Box<dyn std::error::Error>> {
+        let fixtures = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let expected = [
+            ("gps-ne.jpg", 52.5163, 13.3777, 34.0),
+            ("gps-nw.jpg", 36.2297, -116.7672, -86.0),
+            ("gps-se.jpg", -33.8568, 151.2153, 5.0),
+            ("gps-sw.jpg", -22.9519, -43.2105, 700.0),
+        ];
+
+        for (name, lat, lng, alt) in expected {
+            let file = std::fs::File::open(fixtures.join(name))?;
+            let data = ExifExtractor::extract(&mut std::io::BufReader::new(file))?;
+
+            assert!((data.lat.unwrap() - lat).abs() < 1e-4, "{name}");
+            assert!((data.lng.unwrap() - lng).abs() < 1e-4, "{name}");
+            assert_eq!(data.alt, Some(alt), "{name}");
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn extract_exposure_settings() -> Result<(), Box<dyn std::error::Error>> {
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures");
+        let (db, _) = crate::db::Database::new();
+        let mut handle = Handle::new(db, path);
+        let entity = Entity::from("exposure.jpg");
+
+        ExifExtractor.extract_exif(&mut handle, &entity)?;
+
+        let fact = |attribute: &str| handle.get(&entity, &attribute.into()).next().cloned();
+        assert_eq!(fact("image/focal_length"), Some("35".into()));
+        assert_eq!(fact("image/focal_length_35mm"), Some("52".into()));
+        assert_eq!(fact("image/aperture"), Some("2.8".into()));
+        assert_eq!(fact("image/exposure_time"), Some("0.004".into()));
+        assert_eq!(fact("image/iso"), Some("400".into()));
+        assert_eq!(fact("image/orientation"), Some("6".into()));
+        assert_eq!(fact("image/flash"), Some("true".into()));
+
+        let lens = Entity::from("XF23mmF2 R WR");
+        assert_eq!(fact("image/lens"), Some(Value::Reference(lens.clone())));
+        assert_eq!(
+            handle.get(&lens, &"device/manufacturer".into()).next(),
+            Some(&"Fujifilm".into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn round_trip_local_time() {
+        let data = ExifData {
+            timestamp: Some(timestamp(None)),
+            ..Default::default()
+        };
+        let local = data.local_time().unwrap();
+
+        let formatted = format_local_time(local).unwrap();
+        assert_eq!(formatted, "2023-07-14T18:30:05.25");
+        assert_eq!(parse_local_time(&formatted).unwrap(), local);
+    }
+}
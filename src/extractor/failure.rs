@@ -0,0 +1,359 @@
+use crate::{
+    db::{Attribute, Entity, Rule, Value, Variable, VariableSetExt},
+    handle::Handle,
+    query,
+};
+use std::{collections::BTreeMap, error::Error, fmt, io, num, time::Duration};
+
+/// Rough classification of an extractor error, used for retry decisions and reporting
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum FailureKind {
+    /// I/O errors which might go away if the operation is repeated
+    Transient,
+    /// Any other I/O error (missing files, permissions, ...)
+    Io,
+    /// The blob does not contain the metadata the extractor was looking for
+    Missing,
+    /// The blob or an input file is malformed or uses an unsupported format
+    Format,
+    /// A value stored in the database could not be parsed
+    Parse,
+    Other,
+}
+
+impl FailureKind {
+    pub fn classify(error: &(dyn Error + 'static)) -> Self {
+        if let Some(error) = error.downcast_ref::<io::Error>() {
+            return Self::from_io(error);
+        }
+
+        if let Some(error) = error.downcast_ref::<exif::Error>() {
+            return match error {
+                exif::Error::Io(error) => Self::from_io(error),
+                exif::Error::NotFound(_) | exif::Error::BlankValue(_) => Self::Missing,
+                _ => Self::Format,
+            };
+        }
+
+        if let Some(error) = error.downcast_ref::<image::ImageError>() {
+            return match error {
+                image::ImageError::IoError(error) => Self::from_io(error),
+                _ => Self::Format,
+            };
+        }
+
+        if error.is::<csv::Error>() {
+            return Self::Format;
+        }
+
+        if error.is::<num::ParseIntError>()
+            || error.is::<num::ParseFloatError>()
+            || error.is::<time::error::Parse>()
+        {
+            return Self::Parse;
+        }
+
+        Self::Other
+    }
+
+    fn from_io(error: &io::Error) -> Self {
+        match error.kind() {
+            io::ErrorKind::Interrupted | io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut => {
+                Self::Transient
+            }
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => Self::Format,
+            _ => Self::Io,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Transient => "transient",
+            Self::Io => "io",
+            Self::Missing => "missing",
+            Self::Format => "format",
+            Self::Parse => "parse",
+            Self::Other => "other",
+        }
+    }
+
+    fn parse(s: &str) -> Self {
+        match s {
+            "transient" => Self::Transient,
+            "io" => Self::Io,
+            "missing" => Self::Missing,
+            "format" => Self::Format,
+            "parse" => Self::Parse,
+            _ => Self::Other,
+        }
+    }
+}
+
+impl fmt::Display for FailureKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+/// Decides how often a failing extractor call is repeated before it is given up on
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    pub max_attempts: u32,
+    pub backoff: Duration,
+    pub kinds: Vec<FailureKind>,
+}
+
+impl RetryPolicy {
+    /// Never retries, every error goes straight to the dead-letters
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            backoff: Duration::ZERO,
+            kinds: Vec::new(),
+        }
+    }
+
+    pub fn should_retry(&self, kind: FailureKind, attempt: u32) -> bool {
+        attempt < self.max_attempts && self.kinds.contains(&kind)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            backoff: Duration::from_millis(10),
+            kinds: vec![FailureKind::Transient],
+        }
+    }
+}
+
+/// Dead-letter entry for an extractor call that did not succeed, even after retrying.
+///
+/// Failures are stored as regular facts on a `failure:<extractor>:<entity>` entity so they
+/// can be queried like everything else, e.g. to find all blobs that could not be parsed:
+///
+/// ```text
+/// { #failure, :"failure/extractor", ?"exif" },
+/// { #failure, :"failure/entity", #blob }
+/// ```
+#[derive(Debug, Clone)]
+pub struct Failure {
+    pub extractor: String,
+    /// Entity which was being processed, `None` if the extractor failed to initialize
+    pub entity: Option<Entity>,
+    /// Fact that triggered the extractor call, allows replaying it later on
+    pub trigger: Option<(Attribute, Value)>,
+    pub kind: FailureKind,
+    pub message: String,
+    pub attempts: u32,
+}
+
+impl Failure {
+    pub fn new(
+        extractor: impl Into<String>,
+        entity: Option<&Entity>,
+        trigger: Option<(&Attribute, &Value)>,
+        error: &(dyn Error + 'static),
+        attempts: u32,
+    ) -> Self {
+        Self {
+            extractor: extractor.into(),
+            entity: entity.cloned(),
+            trigger: trigger.map(|(a, v)| (a.clone(), v.clone())),
+            kind: FailureKind::classify(error),
+            message: error.to_string(),
+            attempts,
+        }
+    }
+
+    pub fn id(&self) -> Entity {
+        match &self.entity {
+            Some(entity) => Entity::from(format!("failure:{}:{}", self.extractor, entity.0)),
+            None => Entity::from(format!("failure:{}", self.extractor)),
+        }
+    }
+
+    /// Stores the failure in the database. Only the first failure per extractor and entity
+    /// is kept, subsequent ones are most likely caused by the same underlying issue.
+    pub fn record(&self, handle: &mut Handle) {
+        let id = self.id();
+
+        if handle
+            .get(&id, &"failure/extractor".into())
+            .next()
+            .is_some()
+        {
+            return;
+        }
+
+        handle.insert(id.clone(), "failure/extractor", self.extractor.as_str());
+        handle.insert(id.clone(), "failure/kind", self.kind.as_str());
+        handle.insert(id.clone(), "failure/message", self.message.as_str());
+        handle.insert(id.clone(), "failure/attempts", self.attempts);
+
+        if let Some((attribute, value)) = &self.trigger {
+            handle.insert(id.clone(), "failure/attribute", attribute.0.as_str());
+            handle.insert(id.clone(), "failure/value", value.clone());
+        }
+
+        if let Some(entity) = &self.entity {
+            handle.insert(id, "failure/entity", entity);
+        }
+    }
+
+    /// Reads all recorded failures back from the database
+    pub fn load_all(handle: &Handle) -> Vec<Self> {
+        query!(handle where (#failure, ?extractor) match [
+            { #failure, :"failure/extractor", ?extractor }
+        ] => failures);
+
+        failures
+            .iter()
+            .filter_map(|set| {
+                let id = set.get(&failure)?;
+                let extractor = set.get(&extractor)?.data().to_owned();
+                Some(Self::load(handle, id, extractor))
+            })
+            .collect()
+    }
+
+    fn load(handle: &Handle, id: &Entity, extractor: String) -> Self {
+        let data = |attribute: &str| {
+            handle
+                .get(id, &attribute.into())
+                .find_map(|value| match value {
+                    Value::Data(data) => Some(data.clone()),
+                    Value::Reference(_) => None,
+                })
+                .unwrap_or_default()
+        };
+
+        let entity = handle
+            .get(id, &"failure/entity".into())
+            .find_map(|value| match value {
+                Value::Reference(entity) => Some(entity.clone()),
+                Value::Data(_) => None,
+            });
+
+        let trigger = handle
+            .get(id, &"failure/value".into())
+            .next()
+            .map(|value| (Attribute::from(data("failure/attribute")), value.clone()));
+
+        Self {
+            extractor,
+            entity,
+            trigger,
+            kind: FailureKind::parse(&data("failure/kind")),
+            message: data("failure/message"),
+            attempts: data("failure/attempts").parse().unwrap_or(1),
+        }
+    }
+}
+
+/// Summary of all failures stored in a database, grouped by extractor and kind
+#[derive(Debug, Default)]
+pub struct FailureReport {
+    pub failures: Vec<Failure>,
+}
+
+impl FailureReport {
+    pub fn load(handle: &Handle) -> Self {
+        Self {
+            failures: Failure::load_all(handle),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.failures.is_empty()
+    }
+
+    pub fn counts(&self) -> BTreeMap<(&str, FailureKind), usize> {
+        let mut counts = BTreeMap::new();
+
+        for failure in self.failures.iter() {
+            *counts
+                .entry((failure.extractor.as_str(), failure.kind))
+                .or_default() += 1;
+        }
+
+        counts
+    }
+}
+
+impl fmt::Display for FailureReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.is_empty() {
+            return writeln!(f, "No extractor failures");
+        }
+
+        writeln!(f, "{} extractor failures", self.failures.len())?;
+
+        for ((extractor, kind), count) in self.counts() {
+            writeln!(f, "{count: >6} {extractor} ({kind})")?;
+
+            for failure in self.failures.iter() {
+                if failure.extractor == extractor && failure.kind == kind {
+                    let entity = failure
+                        .entity
+                        .as_ref()
+                        .map(|e| e.0.as_str())
+                        .unwrap_or("<init>");
+
+                    writeln!(f, "         {entity}: {}", failure.message)?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod does {
+    use super::*;
+    use crate::db::Database;
+
+    #[test]
+    fn record_and_load_failures() {
+        let (db, _) = Database::new();
+        let mut handle = Handle::new(db, std::env::temp_dir());
+        let entity = Entity::from("image.jpg");
+        let error = exif::Error::NotFound("JPEG");
+
+        let failure = Failure::new(
+            "exif",
+            Some(&entity),
+            Some((&"type/mime".into(), &"image/jpeg".into())),
+            &error,
+            1,
+        );
+        failure.record(&mut handle);
+        failure.record(&mut handle);
+
+        let report = FailureReport::load(&handle);
+        assert_eq!(report.failures.len(), 1);
+
+        let loaded = &report.failures[0];
+        assert_eq!(loaded.extractor, "exif");
+        assert_eq!(loaded.entity, Some(entity));
+        assert_eq!(loaded.kind, FailureKind::Missing);
+        assert_eq!(loaded.message, error.to_string());
+        assert_eq!(
+            loaded.trigger,
+            Some(("type/mime".into(), "image/jpeg".into()))
+        );
+    }
+
+    #[test]
+    fn only_retry_transient_errors() {
+        let policy = RetryPolicy::default();
+        let interrupted = io::Error::from(io::ErrorKind::Interrupted);
+        let missing = io::Error::from(io::ErrorKind::NotFound);
+
+        assert!(policy.should_retry(FailureKind::classify(&interrupted), 1));
+        assert!(!policy.should_retry(FailureKind::classify(&interrupted), 3));
+        assert!(!policy.should_retry(FailureKind::classify(&missing), 1));
+    }
+}
@@ -1,4 +1,4 @@
-use super::Extractor;
+use super::{exif::parse_local_time, Extractor};
 use crate::{
     db::{Attribute, Entity, Rule, Value, Variable, VariableSetExt},
     handle::Handle,
@@ -6,13 +6,67 @@ use crate::{
 };
 use rstar::{primitives::GeomWithData, RTree};
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, error::Error, fmt, path::Path};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+    time::UNIX_EPOCH,
+};
+use time::format_description::well_known::Rfc3339;
+use time_tz::{timezones, PrimitiveDateTimeExt};
+
+/// Mean earth radius in meters
+const EARTH_RADIUS: f64 = 6_371_008.8;
+
+/// Geonames are indexed as points on the unit sphere, so that the nearest neighbor by straight
+/// line distance is also the nearest one along the surface, even across the antimeridian
+type GeonameLocation = GeomWithData<[f64; 3], i64>;
+
+/// Identifies cache files, bump the version whenever the layout of the cached types changes
+const CACHE_MAGIC: &[u8; 8] = b"FIRNGEO\x02";
 
-type GeonameLocation = GeomWithData<[f64; 2], i64>;
+/// Everything expensive to rebuild: both trees, the geonames, their parents and labels (which
+/// are not part of the main dump) and the administrative areas
+type Snapshot = (
+    RTree<GeonameLocation>,
+    RTree<GeonameLocation>,
+    HashMap<i64, Geoname>,
+    Vec<(i64, i64)>,
+    Vec<(i64, HashMap<String, String>)>,
+    HashMap<String, AdminArea>,
+    HashMap<i64, String>,
+);
+
+/// The dumps a GeoNames index is built from, see <https://download.geonames.org/export/dump/>
+#[derive(Debug, Clone, Default)]
+pub struct GeoNamesDumps {
+    /// Main dump, e.g. `allCountries.txt` or `DE.txt`
+    pub geonames: PathBuf,
+    pub hierarchy: PathBuf,
+    pub countries: Option<PathBuf>,
+    /// `admin1CodesASCII.txt` and/or `admin2Codes.txt`
+    pub admin_codes: Vec<PathBuf>,
+    pub alternate_names: Option<PathBuf>,
+    /// Feature classes indexed in addition to places
+    pub features: Vec<FeatureClass>,
+}
 
 pub struct GeoNames {
+    /// Populated places and administrative areas
     tree: RTree<GeonameLocation>,
+    /// Landmarks and natural features, empty unless requested while loading
+    features: RTree<GeonameLocation>,
     geonames: HashMap<i64, Geoname>,
+    /// Countries and administrative divisions by their code, e.g. `DE` or `DE.16.00`
+    admin_areas: HashMap<String, AdminArea>,
+    admin_codes: HashMap<i64, String>,
+    max_distance: f64,
+    max_feature_distance: f64,
+    population_weight: f64,
 }
 
 impl GeoNames {
@@ -20,11 +74,304 @@ impl GeoNames {
         geonames: impl AsRef<Path>,
         hierarchy: impl AsRef<Path>,
     ) -> Result<Self, Box<dyn Error>> {
-        let mut instance = Self::load_geonames(geonames)?;
+        Self::load_with_features(geonames, hierarchy, &[])
+    }
+
+    /// Like [`GeoNames::load`], but additionally indexes geonames of the given classes (e.g.
+    /// landmarks or mountains), which are linked as `location/feature`
+    pub fn load_with_features(
+        geonames: impl AsRef<Path>,
+        hierarchy: impl AsRef<Path>,
+        features: &[FeatureClass],
+    ) -> Result<Self, Box<dyn Error>> {
+        let mut instance = Self::load_geonames(geonames, features)?;
         instance.load_hierarchy(hierarchy)?;
         Ok(instance)
     }
 
+    /// Loads the main dump and hierarchy along with all the optional dumps given
+    pub fn load_dumps(dumps: &GeoNamesDumps) -> Result<Self, Box<dyn Error>> {
+        let mut instance =
+            Self::load_with_features(&dumps.geonames, &dumps.hierarchy, &dumps.features)?;
+
+        if let Some(path) = &dumps.countries {
+            instance = instance.with_countries(path)?;
+        }
+
+        for path in &dumps.admin_codes {
+            instance = instance.with_admin_codes(path)?;
+        }
+
+        if let Some(path) = &dumps.alternate_names {
+            instance = instance.with_alternate_names(path)?;
+        }
+
+        Ok(instance)
+    }
+
+    /// Like [`GeoNames::load_dumps`], but keeps the loaded index in a binary cache file, which
+    /// is read back in one go instead of parsing the dumps again. The cache is rebuilt
+    /// automatically whenever any of the dumps is replaced or the set of dumps changes.
+    pub fn load_cached(
+        dumps: &GeoNamesDumps,
+        cache: impl AsRef<Path>,
+    ) -> Result<Self, Box<dyn Error>> {
+        let key = cache_key(dumps)?;
+
+        if let Some(instance) = Self::read_cache(cache.as_ref(), &key)? {
+            return Ok(instance);
+        }
+
+        let instance = Self::load_dumps(dumps)?;
+        instance.write_cache(cache.as_ref(), &key)?;
+        Ok(instance)
+    }
+
+    fn read_cache(path: &Path, key: &[u8]) -> Result<Option<Self>, Box<dyn Error>> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let Some(payload) = bytes
+            .strip_prefix(CACHE_MAGIC)
+            .and_then(|rest| rest.strip_prefix(key))
+        else {
+            return Ok(None);
+        };
+
+        // Unreadable caches are just rebuilt
+        let Ok((tree, features, mut geonames, parents, labels, admin_areas, admin_codes)) =
+            bincode::deserialize::<Snapshot>(payload)
+        else {
+            return Ok(None);
+        };
+
+        for (child, parent) in parents {
+            if let Some(geoname) = geonames.get_mut(&child) {
+                geoname.parent = Some(parent);
+            }
+        }
+
+        for (id, names) in labels {
+            if let Some(geoname) = geonames.get_mut(&id) {
+                geoname.labels = names;
+            }
+        }
+
+        Ok(Some(Self {
+            admin_areas,
+            admin_codes,
+            ..Self::new(tree, features, geonames)
+        }))
+    }
+
+    fn write_cache(&self, path: &Path, key: &[u8]) -> Result<(), Box<dyn Error>> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let parents: Vec<(i64, i64)> = self
+            .geonames
+            .values()
+            .filter_map(|geoname| Some((geoname.geonameid, geoname.parent?)))
+            .collect();
+
+        let labels: Vec<(i64, &HashMap<String, String>)> = self
+            .geonames
+            .values()
+            .filter(|geoname| !geoname.labels.is_empty())
+            .map(|geoname| (geoname.geonameid, &geoname.labels))
+            .collect();
+
+        // Write to a temporary file first so readers never see a partial cache
+        let temporary = path.with_extension("tmp");
+        let mut writer = BufWriter::new(File::create(&temporary)?);
+        writer.write_all(CACHE_MAGIC)?;
+        writer.write_all(key)?;
+        bincode::serialize_into(
+            &mut writer,
+            &(
+                &self.tree,
+                &self.features,
+                &self.geonames,
+                parents,
+                labels,
+                &self.admin_areas,
+                &self.admin_codes,
+            ),
+        )?;
+        writer
+            .into_inner()
+            .map_err(|e| e.into_error())?
+            .sync_all()?;
+        fs::rename(temporary, path)?;
+
+        Ok(())
+    }
+
+    /// Maximum distance in meters between a coordinate and the geoname it is linked to,
+    /// defaults to 25 km
+    pub fn with_max_distance(mut self, max_distance: f64) -> Self {
+        self.max_distance = max_distance;
+        self
+    }
+
+    /// Maximum distance in meters between a coordinate and the feature it is linked to,
+    /// defaults to 2 km
+    pub fn with_max_feature_distance(mut self, max_distance: f64) -> Self {
+        self.max_feature_distance = max_distance;
+        self
+    }
+
+    /// How much larger places are preferred over closer ones. Distances are divided by
+    /// `1 + weight * log10(1 + population)`, so with the default of 0.1 a city of a million
+    /// people wins against a hamlet 1.6 times closer.
+    pub fn with_population_weight(mut self, weight: f64) -> Self {
+        self.population_weight = weight;
+        self
+    }
+
+    /// Reads multilingual names from an `alternateNamesV2.txt` dump, which are added as
+    /// `text/label@<language>` facts
+    pub fn with_alternate_names(mut self, path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .delimiter(b'\t')
+            .flexible(true)
+            .quoting(false)
+            .from_path(path)?;
+
+        for result in rdr.into_deserialize() {
+            let entry: AlternateName = result?;
+
+            let Some(language) = entry.isolanguage.filter(|language| {
+                // Pseudo languages are used for links, postal codes and the likes
+                !language.is_empty() && !PSEUDO_LANGUAGES.contains(&language.as_str())
+            }) else {
+                continue;
+            };
+
+            if is_set(&entry.is_historic) || is_set(&entry.is_colloquial) {
+                continue;
+            }
+
+            if let Some(geoname) = self.geonames.get_mut(&entry.geonameid) {
+                if is_set(&entry.is_preferred_name) {
+                    geoname.labels.insert(language, entry.alternate_name);
+                } else {
+                    geoname
+                        .labels
+                        .entry(language)
+                        .or_insert(entry.alternate_name);
+                }
+            }
+        }
+
+        Ok(self)
+    }
+
+    /// Reads countries from a `countryInfo.txt` dump, which become the roots of the
+    /// administrative hierarchy
+    pub fn with_countries(mut self, path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .delimiter(b'\t')
+            .flexible(true)
+            .quoting(false)
+            .comment(Some(b'#'))
+            .from_path(path)?;
+
+        for result in rdr.into_records() {
+            let record = result?;
+
+            let (Some(iso), Some(name), Some(Ok(geonameid))) = (
+                record.get(0),
+                record.get(4),
+                record.get(16).map(str::parse::<i64>),
+            ) else {
+                continue;
+            };
+
+            self.insert_admin_area(AdminArea {
+                geonameid,
+                code: iso.to_owned(),
+                iso3: record.get(1).map(str::to_owned),
+                name: name.to_owned(),
+            });
+        }
+
+        Ok(self)
+    }
+
+    /// Reads administrative divisions from an `admin1CodesASCII.txt` or `admin2Codes.txt` dump.
+    /// Places are attached to the most specific division matching their admin codes, unless
+    /// `hierarchy.txt` says otherwise.
+    pub fn with_admin_codes(mut self, path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+        let rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .delimiter(b'\t')
+            .flexible(true)
+            .quoting(false)
+            .from_path(path)?;
+
+        for result in rdr.into_records() {
+            let record = result?;
+
+            let (Some(code), Some(name), Some(Ok(geonameid))) = (
+                record.get(0),
+                record.get(1),
+                record.get(3).map(str::parse::<i64>),
+            ) else {
+                continue;
+            };
+
+            self.insert_admin_area(AdminArea {
+                geonameid,
+                code: code.to_owned(),
+                iso3: None,
+                name: name.to_owned(),
+            });
+        }
+
+        Ok(self)
+    }
+
+    fn insert_admin_area(&mut self, area: AdminArea) {
+        self.admin_codes.insert(area.geonameid, area.code.clone());
+        self.admin_areas.insert(area.code.clone(), area);
+    }
+
+    /// Parent of a geoname, either from `hierarchy.txt` or derived from its admin codes
+    fn parent(&self, id: i64) -> Option<i64> {
+        let geoname = self.geonames.get(&id);
+
+        if let Some(parent) = geoname.and_then(|geoname| geoname.parent) {
+            return Some(parent);
+        }
+
+        let segments: Vec<&str> = match (geoname, self.admin_codes.get(&id)) {
+            (_, Some(code)) => code.split('.').collect(),
+            (Some(geoname), None) => [
+                &geoname.country_code,
+                &geoname.admin1_code,
+                &geoname.admin2_code,
+            ]
+            .into_iter()
+            .map_while(|code| code.as_deref().filter(|code| !code.is_empty()))
+            .collect(),
+            (None, None) => return None,
+        };
+
+        // Most specific first, skipping the geoname itself in case it is an admin area
+        (1..=segments.len())
+            .rev()
+            .filter_map(|len| self.admin_areas.get(&segments[..len].join(".")))
+            .map(|area| area.geonameid)
+            .find(|parent| *parent != id)
+    }
+
     fn load_hierarchy(&mut self, path: impl AsRef<Path>) -> Result<(), Box<dyn Error>> {
         let rdr = csv::ReaderBuilder::new()
             .has_headers(false)
@@ -47,36 +394,63 @@ impl GeoNames {
         Ok(())
     }
 
-    fn load_geonames(path: impl AsRef<Path>) -> Result<Self, Box<dyn Error>> {
+    fn load_geonames(
+        path: impl AsRef<Path>,
+        features: &[FeatureClass],
+    ) -> Result<Self, Box<dyn Error>> {
         let rdr = csv::ReaderBuilder::new()
             .has_headers(false)
             .delimiter(b'\t')
             .flexible(true)
+            .quoting(false)
             .from_path(path)?;
 
-        let mut entries = Vec::new();
+        let mut places = Vec::new();
+        let mut landmarks = Vec::new();
         let mut geonames = HashMap::new();
 
         for result in rdr.into_deserialize() {
             let geoname: Geoname = result?;
+            let location = GeonameLocation::new(
+                to_cartesian(geoname.latitude, geoname.longitude),
+                geoname.geonameid,
+            );
 
-            if ![FeatureClass::P, FeatureClass::A].contains(&geoname.feature_class) {
+            if [FeatureClass::P, FeatureClass::A].contains(&geoname.feature_class) {
+                places.push(location);
+            } else if features.contains(&geoname.feature_class) {
+                landmarks.push(location);
+            } else {
                 continue;
             }
 
-            entries.push(GeonameLocation::new(
-                [geoname.latitude, geoname.longitude],
-                geoname.geonameid,
-            ));
-
             geonames.insert(geoname.geonameid, geoname);
         }
 
-        let tree = RTree::bulk_load(entries);
+        eprintln!("Imported {} geonames", geonames.len());
 
-        println!("Imported {} geonames", geonames.len());
+        Ok(Self::new(
+            RTree::bulk_load(places),
+            RTree::bulk_load(landmarks),
+            geonames,
+        ))
+    }
 
-        Ok(Self { tree, geonames })
+    fn new(
+        tree: RTree<GeonameLocation>,
+        features: RTree<GeonameLocation>,
+        geonames: HashMap<i64, Geoname>,
+    ) -> Self {
+        Self {
+            tree,
+            features,
+            geonames,
+            admin_areas: HashMap::new(),
+            admin_codes: HashMap::new(),
+            max_distance: 25_000.0,
+            max_feature_distance: 2_000.0,
+            population_weight: 0.1,
+        }
     }
 
     fn handle_coordinate(&self, handle: &mut Handle, entity: Entity) {
@@ -85,48 +459,298 @@ impl GeoNames {
             { #entity, :"location/longitude", ?lng }
         ] => coordinates);
 
+        // Parse the coords
+        let Some((Some(lat), Some(lng))) = coordinates.first().map(|c| {
+            (
+                c.get(&lat).and_then(|v| v.data().parse::<f64>().ok()),
+                c.get(&lng).and_then(|v| v.data().parse::<f64>().ok()),
+            )
+        }) else {
+            return;
+        };
+
         // Don't do double work
         if handle
             .get(&entity, &"location/geoname".into())
             .next()
-            .is_some()
+            .is_none()
         {
-            return;
+            // Big places win against small ones at a similar distance
+            let best = self
+                .candidates(&self.tree, self.max_distance, lat, lng)
+                .min_by(|a, b| self.score(*a).total_cmp(&self.score(*b)));
+
+            if let Some((id, distance)) = best {
+                let geoname = Entity::from(format!("geoname:{id}"));
+                handle.insert(entity.clone(), "location/geoname", geoname);
+                handle.insert(
+                    entity.clone(),
+                    "location/geoname_distance",
+                    distance.round(),
+                );
+            }
         }
 
-        // Parse the coords and find the nearest neighbor
-        if let Some((Some(lat), Some(lng))) = coordinates.get(0).map(|c| {
-            (
-                c.get(&lat).map(|v| v.data().parse::<f64>().ok()).flatten(),
-                c.get(&lng).map(|v| v.data().parse::<f64>().ok()).flatten(),
-            )
-        }) {
-            // TODO Filter by distance so we don't get super far away matches if there isn't anything close
-            if let Some(neighbor) = self.tree.nearest_neighbor(&[lat, lng]) {
-                let geoname = Entity::from(format!("geoname:{}", neighbor.data));
-                handle.insert(entity, "location/geoname", geoname);
+        if handle
+            .get(&entity, &"location/feature".into())
+            .next()
+            .is_none()
+        {
+            let nearest = self
+                .candidates(&self.features, self.max_feature_distance, lat, lng)
+                .next();
+
+            if let Some((id, distance)) = nearest {
+                let feature = Entity::from(format!("geoname:{id}"));
+                handle.insert(entity.clone(), "location/feature", feature);
+                handle.insert(entity, "location/feature_distance", distance.round());
             }
         }
     }
 
+    /// Geonames of a tree within a maximum distance of a coordinate along with their
+    /// great-circle distance in meters, nearest first
+    fn candidates<'a>(
+        &'a self,
+        tree: &'a RTree<GeonameLocation>,
+        max_distance: f64,
+        lat: f64,
+        lng: f64,
+    ) -> impl Iterator<Item = (i64, f64)> + 'a {
+        // Chord length on the unit sphere corresponding to the maximum arc length
+        let max_angle = (max_distance / EARTH_RADIUS).min(std::f64::consts::PI);
+        let max_chord = 2.0 * (max_angle / 2.0).sin();
+
+        tree.nearest_neighbor_iter_with_distance_2(&to_cartesian(lat, lng))
+            .take_while(move |(_, chord_2)| *chord_2 <= max_chord * max_chord)
+            .filter_map(move |(location, _)| {
+                let geoname = self.geonames.get(&location.data)?;
+                let distance = haversine((lat, lng), (geoname.latitude, geoname.longitude));
+                Some((location.data, distance))
+            })
+    }
+
+    /// Lower is better
+    fn score(&self, (id, distance): (i64, f64)) -> f64 {
+        let population = self
+            .geonames
+            .get(&id)
+            .and_then(|geoname| geoname.population)
+            .unwrap_or_default()
+            .max(0.0);
+
+        distance / (1.0 + self.population_weight * (1.0 + population).log10())
+    }
+
     fn handle_geoname(&self, handle: &mut Handle, entity: Entity, id: i64) {
-        if let Some(geoname) = self.geonames.get(&id) {
-            // println!("found geoname {id}");
-            // println!("{geoname:?}");
+        let mut next = Some((entity, id));
+
+        // Walk up the hierarchy until reaching an already described ancestor
+        while let Some((entity, id)) = next.take() {
+            // Geonames are referenced over and over again, only describe them once
+            if handle
+                .get(&entity, &"geoname/feature".into())
+                .next()
+                .is_some()
+            {
+                return;
+            }
+
+            let admin_area = self
+                .admin_codes
+                .get(&id)
+                .and_then(|code| self.admin_areas.get(code));
+
+            if let Some(geoname) = self.geonames.get(&id) {
+                self.describe_geoname(handle, &entity, geoname);
+            } else if let Some(area) = admin_area {
+                // Admin areas may be missing from regional dumps
+                let feature = match area.code.matches('.').count() {
+                    0 => "A.PCLI",
+                    1 => "A.ADM1",
+                    _ => "A.ADM2",
+                };
+                handle.insert(entity.clone(), "geoname/feature", feature);
+                handle.insert(entity.clone(), "text/label", area.name.clone());
+            } else {
+                return;
+            }
 
-            if handle.get(&entity, &"text/label".into()).next().is_none() {
-                handle.insert(entity.clone(), "text/label", geoname.name.clone());
+            if let Some(area) = admin_area {
+                handle.insert(entity.clone(), "geoname/admin_code", area.code.clone());
+
+                if let Some(iso3) = &area.iso3 {
+                    handle.insert(entity.clone(), "geoname/iso_code", area.code.clone());
+                    handle.insert(entity.clone(), "geoname/iso3_code", iso3.clone());
+                }
             }
 
-            if let Some(parent_id) = &geoname.parent {
+            if let Some(parent_id) = self.parent(id) {
                 let parent = Entity::from(format!("geoname:{}", parent_id));
-                handle.insert(entity, "relation/parent", parent);
+                handle.insert(entity, "relation/parent", parent.clone());
+                next = Some((parent, parent_id));
+            }
+        }
+    }
+
+    fn describe_geoname(&self, handle: &mut Handle, entity: &Entity, geoname: &Geoname) {
+        handle.insert(
+            entity.clone(),
+            "geoname/feature",
+            format!("{}.{}", geoname.feature_class, geoname.feature_code),
+        );
+
+        if handle.get(entity, &"text/label".into()).next().is_none() {
+            handle.insert(entity.clone(), "text/label", geoname.name.clone());
+        }
+
+        for (language, label) in &geoname.labels {
+            handle.insert(
+                entity.clone(),
+                format!("text/label@{language}"),
+                label.clone(),
+            );
+        }
+
+        for alias in geoname
+            .alternatenames
+            .iter()
+            .flat_map(|names| names.split(','))
+            .filter(|alias| !alias.is_empty() && *alias != geoname.name)
+        {
+            handle.insert(entity.clone(), "text/alias", alias);
+        }
+    }
+
+    /// Fills in `time/creation` for entities which only have a local time, using the timezone
+    /// of their location. Creation times that assumed UTC are replaced.
+    fn handle_timezone(&self, handle: &mut Handle, entity: Entity) -> Result<(), Box<dyn Error>> {
+        let assumed = handle
+            .get(&entity, &"time/assumed_utc".into())
+            .next()
+            .is_some();
+
+        if !assumed
+            && handle
+                .get(&entity, &"time/creation".into())
+                .next()
+                .is_some()
+        {
+            return Ok(());
+        }
+
+        let local = handle
+            .get(&entity, &"time/local".into())
+            .find_map(|value| match value {
+                Value::Data(data) => Some(data.clone()),
+                Value::Reference(_) => None,
+            });
+
+        let timezone = handle
+            .get(&entity, &"location/geoname".into())
+            .find_map(|value| match value {
+                Value::Reference(geoname) => geoname_id(geoname),
+                Value::Data(_) => None,
+            })
+            .and_then(|id| self.geonames.get(&id))
+            .and_then(|geoname| geoname.timezone.as_deref())
+            .and_then(timezones::get_by_name);
+
+        if let (Some(local), Some(timezone)) = (local, timezone) {
+            // Times within a DST transition are ambiguous, just go with the earlier one
+            if let Some(creation) = parse_local_time(&local)?
+                .assume_timezone(timezone)
+                .take_first()
+            {
+                handle.retract_attribute(&entity, &"time/creation".into());
+                handle.retract_attribute(&entity, &"time/assumed_utc".into());
+                handle.insert(entity, "time/creation", creation.format(&Rfc3339)?);
             }
         }
+
+        Ok(())
+    }
+}
+
+/// Hash of everything the cached index is derived from.
+///
+/// Dumps are identified by their location, size and modification time rather than a hash of
+/// their contents: `allCountries.txt` alone is about 1.5 GB, so hashing it would take as long
+/// as the parsing the cache is meant to save. Dumps are replaced by downloading them again,
+/// which always changes the modification time.
+fn cache_key(dumps: &GeoNamesDumps) -> Result<Vec<u8>, io::Error> {
+    let mut hasher = Sha256::new();
+
+    let optional = [
+        ("countries", &dumps.countries),
+        ("alternate_names", &dumps.alternate_names),
+    ];
+    let inputs = [
+        ("geonames", &dumps.geonames),
+        ("hierarchy", &dumps.hierarchy),
+    ]
+    .into_iter()
+    .chain(dumps.admin_codes.iter().map(|path| ("admin_codes", path)))
+    .chain(
+        optional
+            .into_iter()
+            .filter_map(|(name, path)| Some((name, path.as_ref()?))),
+    );
+
+    // Dumps are tagged with their role, so moving one from one role to another is a change too
+    for (name, dump) in inputs {
+        let metadata = fs::metadata(dump)?;
+        let mtime = metadata
+            .modified()?
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+
+        hasher.update(name);
+        hasher.update(fs::canonicalize(dump)?.as_os_str().as_encoded_bytes());
+        hasher.update(metadata.len().to_be_bytes());
+        hasher.update(mtime.as_nanos().to_be_bytes());
+    }
+
+    for feature in &dumps.features {
+        hasher.update(feature.as_str());
     }
+
+    Ok(hasher.finalize().to_vec())
+}
+
+fn to_cartesian(lat: f64, lng: f64) -> [f64; 3] {
+    let (lat, lng) = (lat.to_radians(), lng.to_radians());
+    [lat.cos() * lng.cos(), lat.cos() * lng.sin(), lat.sin()]
+}
+
+/// Great-circle distance in meters between two coordinates given in degrees
+fn haversine((lat1, lng1): (f64, f64), (lat2, lng2): (f64, f64)) -> f64 {
+    let d_lat = (lat2 - lat1).to_radians();
+    let d_lng = (lng2 - lng1).to_radians();
+    let a = (d_lat / 2.0).sin().powi(2)
+        + lat1.to_radians().cos() * lat2.to_radians().cos() * (d_lng / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS * a.sqrt().asin()
+}
+
+/// Values of the `isolanguage` column which aren't languages
+const PSEUDO_LANGUAGES: &[&str] = &[
+    "link", "wkdt", "post", "iata", "icao", "faac", "abbr", "unlc", "tcid",
+];
+
+fn is_set(flag: &Option<String>) -> bool {
+    flag.as_deref() == Some("1")
+}
+
+fn geoname_id(entity: &Entity) -> Option<i64> {
+    entity.0.strip_prefix("geoname:")?.parse().ok()
 }
 
 impl Extractor for GeoNames {
+    fn name(&self) -> &str {
+        "geonames"
+    }
+
     fn entry_added(
         &mut self,
         handle: &mut Handle,
@@ -140,14 +764,15 @@ impl Extractor for GeoNames {
             self.handle_coordinate(handle, entity.to_owned());
         }
 
+        if attribute == &Attribute::from("location/geoname")
+            || attribute == &Attribute::from("time/local")
+        {
+            self.handle_timezone(handle, entity.to_owned())?;
+        }
+
         // This is a bit of a hack because the extractor is not called for ref-only entities
         if let Value::Reference(referenced) = value {
-            if let Some(id) = referenced
-                .0
-                .strip_prefix("geoname:")
-                .map(|id| id.parse::<i64>().ok())
-                .flatten()
-            {
+            if let Some(id) = geoname_id(referenced) {
                 self.handle_geoname(handle, referenced.to_owned(), id);
             }
         }
@@ -163,6 +788,27 @@ struct HierarchyEntry {
     variant: Option<String>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct AdminArea {
+    geonameid: i64,
+    code: String,
+    /// Only set for countries
+    iso3: Option<String>,
+    name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct AlternateName {
+    alternate_name_id: i64,
+    geonameid: i64,
+    isolanguage: Option<String>,
+    alternate_name: String,
+    is_preferred_name: Option<String>,
+    is_short_name: Option<String>,
+    is_colloquial: Option<String>,
+    is_historic: Option<String>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 struct Geoname {
     geonameid: i64,
@@ -186,10 +832,13 @@ struct Geoname {
     modification_date: Option<String>,
     #[serde(skip)]
     parent: Option<i64>,
+    /// Preferred name per language
+    #[serde(skip)]
+    labels: HashMap<String, String>,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
-enum FeatureClass {
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FeatureClass {
     A, // country, state, region,...
     H, // stream, lake, ...
     L, // parks,area, ...
@@ -204,23 +853,335 @@ enum FeatureClass {
 }
 
 impl FeatureClass {
-    fn to_string(&self) -> String {
+    fn as_str(&self) -> &'static str {
         match *self {
-            FeatureClass::A => "A".to_string(),
-            FeatureClass::H => "H".to_string(),
-            FeatureClass::L => "L".to_string(),
-            FeatureClass::P => "P".to_string(),
-            FeatureClass::R => "R".to_string(),
-            FeatureClass::S => "S".to_string(),
-            FeatureClass::T => "T".to_string(),
-            FeatureClass::U => "U".to_string(),
-            FeatureClass::V => "V".to_string(),
-            FeatureClass::Other => "X".to_string(),
+            FeatureClass::A => "A",
+            FeatureClass::H => "H",
+            FeatureClass::L => "L",
+            FeatureClass::P => "P",
+            FeatureClass::R => "R",
+            FeatureClass::S => "S",
+            FeatureClass::T => "T",
+            FeatureClass::U => "U",
+            FeatureClass::V => "V",
+            FeatureClass::Other => "X",
         }
     }
 }
+
 impl fmt::Display for FeatureClass {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        write!(f, "{}", self.to_string())
+        write!(f, "{}", self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod does {
+    use super::*;
+    use crate::db::Database;
+
+    const BERLIN: &str = "2950159\tBerlin\tBerlin\t\t52.52437\t13.41053\tP\tPPLC\tDE\t\t16\t00\t11000\t11000000\t3426354\t74\t43\tEurope/Berlin\t2022-06-06\n";
+
+    #[test]
+    fn only_link_geonames_within_max_distance() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let geonames = dir.path().join("geonames.txt");
+        let hierarchy = dir.path().join("hierarchy.txt");
+        std::fs::write(&geonames, BERLIN)?;
+        std::fs::write(&hierarchy, "")?;
+
+        let mut extractor = GeoNames::load(geonames, hierarchy)?.with_max_distance(10_000.0);
+        let (db, _) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+
+        // Brandenburg Gate is about 2.4 km away, Potsdam about 27 km
+        for (name, lat, lng) in [
+            ("gate", "52.5163", "13.3777"),
+            ("potsdam", "52.3989", "13.0657"),
+        ] {
+            let entity = Entity::from(name);
+            handle.insert(entity.clone(), "location/latitude", lat);
+            handle.insert(entity.clone(), "location/longitude", lng);
+            extractor.entry_added(
+                &mut handle,
+                &entity,
+                &"location/longitude".into(),
+                &lng.into(),
+            )?;
+        }
+
+        let gate = Entity::from("gate");
+        assert_eq!(
+            handle.get(&gate, &"location/geoname".into()).next(),
+            Some(&Value::Reference("geoname:2950159".into()))
+        );
+        let distance: f64 = handle
+            .get(&gate, &"location/geoname_distance".into())
+            .next()
+            .unwrap()
+            .data()
+            .parse()?;
+        assert!((2_300.0..2_500.0).contains(&distance), "{distance}");
+
+        let potsdam = Entity::from("potsdam");
+        assert!(handle
+            .get(&potsdam, &"location/geoname".into())
+            .next()
+            .is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn prefer_large_places_and_link_features() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let geonames = dir.path().join("geonames.txt");
+        let hierarchy = dir.path().join("hierarchy.txt");
+        let alternate_names = dir.path().join("alternateNames.txt");
+        std::fs::write(
+            &geonames,
+            [
+                BERLIN,
+                "100\tBrandenburger Tor\tBrandenburger Tor\tBrandenburg Gate,Porte de Brandebourg\t52.5163\t13.3777\tS\tMNMT\tDE\t\t16\t00\t11000\t11000000\t0\t\t34\tEurope/Berlin\t2022-06-06\n",
+                "200\tWeiler\tWeiler\t\t52.52\t13.37\tP\tPPLL\tDE\t\t16\t00\t11000\t11000000\t10\t\t34\tEurope/Berlin\t2022-06-06\n",
+            ]
+            .concat(),
+        )?;
+        std::fs::write(&hierarchy, "")?;
+        std::fs::write(
+            &alternate_names,
+            "1\t100\ten\tBrandenburg Gate\t\t\t\t\t\t\n\
+             2\t100\tde\tBrandenburger Thor\t\t\t\t1\t\t\n\
+             3\t100\tde\tBrandenburger Tor\t1\t\t\t\t\t\n\
+             4\t100\tlink\thttps://en.wikipedia.org/wiki/Brandenburg_Gate\t\t\t\t\t\t\n",
+        )?;
+
+        let mut extractor = GeoNames::load_with_features(geonames, hierarchy, &[FeatureClass::S])?
+            .with_alternate_names(alternate_names)?;
+        let (db, _) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+        let entity = Entity::from("image.jpg");
+
+        // Slightly closer to the hamlet than to the center of Berlin
+        handle.insert(entity.clone(), "location/latitude", "52.52");
+        handle.insert(entity.clone(), "location/longitude", "13.39");
+        extractor.entry_added(
+            &mut handle,
+            &entity,
+            &"location/longitude".into(),
+            &"13.39".into(),
+        )?;
+
+        assert_eq!(
+            handle.get(&entity, &"location/geoname".into()).next(),
+            Some(&Value::Reference("geoname:2950159".into()))
+        );
+
+        let feature = Value::Reference("geoname:100".into());
+        assert_eq!(
+            handle.get(&entity, &"location/feature".into()).next(),
+            Some(&feature)
+        );
+
+        extractor.entry_added(&mut handle, &entity, &"location/feature".into(), &feature)?;
+        let gate = Entity::from("geoname:100");
+        assert_eq!(
+            handle
+                .get(&gate, &"text/label@de".into())
+                .collect::<Vec<_>>(),
+            vec![&Value::from("Brandenburger Tor")]
+        );
+        assert_eq!(
+            handle.get(&gate, &"text/label@en".into()).next(),
+            Some(&"Brandenburg Gate".into())
+        );
+        assert!(handle
+            .get(&gate, &"text/label@link".into())
+            .next()
+            .is_none());
+        assert_eq!(handle.get(&gate, &"text/alias".into()).count(), 2);
+        assert_eq!(
+            handle.get(&gate, &"geoname/feature".into()).next(),
+            Some(&"S.MNMT".into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn derive_hierarchy_from_admin_codes() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let geonames = dir.path().join("geonames.txt");
+        let hierarchy = dir.path().join("hierarchy.txt");
+        let countries = dir.path().join("countryInfo.txt");
+        let admin1 = dir.path().join("admin1CodesASCII.txt");
+        let admin2 = dir.path().join("admin2Codes.txt");
+        std::fs::write(&geonames, BERLIN)?;
+        std::fs::write(&hierarchy, "")?;
+        std::fs::write(
+            &countries,
+            "#ISO\tISO3\tISO-Numeric\tfips\tCountry\tCapital\tArea(in sq km)\tPopulation\tContinent\ttld\tCurrencyCode\tCurrencyName\tPhone\tPostal Code Format\tPostal Code Regex\tLanguages\tgeonameid\tneighbours\tEquivalentFipsCode\n\
+             DE\tDEU\t276\tGM\tGermany\tBerlin\t357021\t82927922\tEU\t.de\tEUR\tEuro\t49\t#####\t^(\\d{5})$\tde\t2921044\tCH,PL\t\n",
+        )?;
+        std::fs::write(&admin1, "DE.16\tLand Berlin\tLand Berlin\t2950157\n")?;
+        std::fs::write(&admin2, "DE.16.00\tBerlin, Stadt\tBerlin, Stadt\t6547383\n")?;
+
+        let mut extractor = GeoNames::load(geonames, hierarchy)?
+            .with_countries(countries)?
+            .with_admin_codes(admin1)?
+            .with_admin_codes(admin2)?;
+        let (db, _) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+        let entity = Entity::from("image.jpg");
+        let geoname = Value::Reference("geoname:2950159".into());
+
+        handle.insert(entity.clone(), "location/geoname", geoname.clone());
+        extractor.entry_added(&mut handle, &entity, &"location/geoname".into(), &geoname)?;
+
+        let parent = |handle: &Handle, id: &str| match handle
+            .get(&Entity::from(id), &"relation/parent".into())
+            .next()
+        {
+            Some(Value::Reference(parent)) => Some(parent.0.clone()),
+            _ => None,
+        };
+
+        assert_eq!(
+            parent(&handle, "geoname:2950159").as_deref(),
+            Some("geoname:6547383")
+        );
+        assert_eq!(
+            parent(&handle, "geoname:6547383").as_deref(),
+            Some("geoname:2950157")
+        );
+        assert_eq!(
+            parent(&handle, "geoname:2950157").as_deref(),
+            Some("geoname:2921044")
+        );
+        assert_eq!(parent(&handle, "geoname:2921044"), None);
+
+        let state = Entity::from("geoname:2950157");
+        assert_eq!(
+            handle.get(&state, &"text/label".into()).next(),
+            Some(&"Land Berlin".into())
+        );
+        assert_eq!(
+            handle.get(&state, &"geoname/admin_code".into()).next(),
+            Some(&"DE.16".into())
+        );
+
+        let country = Entity::from("geoname:2921044");
+        assert_eq!(
+            handle.get(&country, &"geoname/iso_code".into()).next(),
+            Some(&"DE".into())
+        );
+        assert_eq!(
+            handle.get(&country, &"geoname/iso3_code".into()).next(),
+            Some(&"DEU".into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn rebuild_cache_when_dump_changes() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let geonames = dir.path().join("geonames.txt");
+        let hierarchy = dir.path().join("hierarchy.txt");
+        let cache = dir.path().join("cache/geonames.bin");
+        let admin1 = dir.path().join("admin1CodesASCII.txt");
+        let alternate_names = dir.path().join("alternateNamesV2.txt");
+        std::fs::write(&geonames, BERLIN)?;
+        std::fs::write(&hierarchy, "")?;
+        std::fs::write(&admin1, "DE.16\tLand Berlin\tLand Berlin\t2950157\n")?;
+        std::fs::write(&alternate_names, "1\t2950159\ten\tBerlin\t1\t\t\t\n")?;
+
+        let mut dumps = GeoNamesDumps {
+            geonames: geonames.clone(),
+            hierarchy: hierarchy.clone(),
+            admin_codes: vec![admin1],
+            ..Default::default()
+        };
+
+        let loaded = GeoNames::load_cached(&dumps, &cache)?;
+        assert_eq!(loaded.geonames.len(), 1);
+
+        let cached =
+            GeoNames::read_cache(&cache, &cache_key(&dumps)?)?.expect("cache should be valid");
+        assert_eq!(cached.tree.size(), 1);
+        assert!(cached.geonames.contains_key(&2950159));
+        assert_eq!(cached.admin_codes[&2950157], "DE.16");
+
+        // A different set of features or dumps is a different index
+        let features = GeoNamesDumps {
+            features: vec![FeatureClass::S],
+            ..dumps.clone()
+        };
+        assert!(GeoNames::read_cache(&cache, &cache_key(&features)?)?.is_none());
+
+        dumps.alternate_names = Some(alternate_names);
+        assert!(GeoNames::read_cache(&cache, &cache_key(&dumps)?)?.is_none());
+
+        GeoNames::load_cached(&dumps, &cache)?;
+        let cached =
+            GeoNames::read_cache(&cache, &cache_key(&dumps)?)?.expect("cache should be valid");
+        assert_eq!(cached.geonames[&2950159].labels["en"], "Berlin");
+
+        std::fs::write(
+            &geonames,
+            [
+                BERLIN,
+                "200\tWeiler\tWeiler\t\t52.52\t13.37\tP\tPPLL\tDE\t\t16\t00\t11000\t11000000\t10\t\t34\tEurope/Berlin\t2022-06-06\n",
+            ]
+            .concat(),
+        )?;
+        std::fs::write(&hierarchy, "2950159\t200\tADM\n")?;
+
+        let reloaded = GeoNames::load_cached(&dumps, &cache)?;
+        assert_eq!(reloaded.tree.size(), 2);
+
+        let cached =
+            GeoNames::read_cache(&cache, &cache_key(&dumps)?)?.expect("cache should be rebuilt");
+        assert_eq!(cached.geonames[&200].parent, Some(2950159));
+
+        Ok(())
+    }
+
+    #[test]
+    fn compute_great_circle_distances() {
+        // Berlin to Paris is about 878 km
+        let distance = haversine((52.52437, 13.41053), (48.85341, 2.3488));
+        assert!((875_000.0..880_000.0).contains(&distance), "{distance}");
+
+        // Neighbors across the antimeridian are close
+        let distance = haversine((0.0, 179.9), (0.0, -179.9));
+        assert!((22_000.0..23_000.0).contains(&distance), "{distance}");
+    }
+
+    #[test]
+    fn derive_creation_time_from_timezone() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let geonames = dir.path().join("geonames.txt");
+        let hierarchy = dir.path().join("hierarchy.txt");
+        std::fs::write(&geonames, BERLIN)?;
+        std::fs::write(&hierarchy, "")?;
+
+        let mut extractor = GeoNames::load(geonames, hierarchy)?;
+        let (db, _) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+        let entity = Entity::from("image.jpg");
+        let geoname = Value::Reference(Entity::from("geoname:2950159"));
+
+        handle.insert(entity.clone(), "time/local", "2023-01-14T18:30:05");
+        handle.insert(entity.clone(), "time/creation", "2023-01-14T18:30:05Z");
+        handle.insert(entity.clone(), "time/assumed_utc", "true");
+        handle.insert(entity.clone(), "location/geoname", geoname.clone());
+        extractor.entry_added(&mut handle, &entity, &"location/geoname".into(), &geoname)?;
+
+        let creation: Vec<_> = handle.get(&entity, &"time/creation".into()).collect();
+        assert_eq!(creation, [&Value::from("2023-01-14T18:30:05+01:00")]);
+        assert_eq!(handle.get(&entity, &"time/assumed_utc".into()).next(), None);
+
+        Ok(())
     }
 }
@@ -3,17 +3,124 @@ use crate::{
     db::{Attribute, Entity, Value},
     handle::Handle,
 };
-use std::io::Read;
+use std::{collections::HashMap, io::Read, path::Path};
 
-pub struct MimeInfer;
+/// Checks whether the start of a file belongs to a certain type
+pub type Matcher = fn(&[u8]) -> bool;
+
+/// Infers the MIME type of blobs from their contents, falling back to their file extension.
+///
+/// Registered matchers are tried first, in order, followed by the ones built into `infer`.
+/// This allows detecting formats `infer` does not know or misclassifies, e.g. camera RAW
+/// files which are mostly TIFF files with a few extra tags.
+pub struct MimeInfer {
+    matchers: Vec<(String, Matcher)>,
+    extensions: HashMap<String, String>,
+    sniff_size: u64,
+}
 
 impl MimeInfer {
+    pub fn new() -> Self {
+        let mut instance = Self {
+            matchers: Vec::new(),
+            extensions: HashMap::new(),
+            sniff_size: 8192,
+        };
+
+        for (mime, matcher) in [
+            ("image/x-canon-cr3", matchers::cr3 as Matcher),
+            ("image/x-canon-cr2", matchers::cr2),
+            ("image/x-adobe-dng", matchers::dng),
+            ("image/x-nikon-nef", matchers::nef),
+            ("image/x-sony-arw", matchers::arw),
+            ("image/x-fuji-raf", matchers::raf),
+            ("image/avif", matchers::avif),
+            ("image/heic", matchers::heic),
+            ("image/heif", matchers::heif),
+        ] {
+            instance.matchers.push((mime.to_owned(), matcher));
+        }
+
+        for (extension, mime) in [
+            ("jpg", "image/jpeg"),
+            ("jpeg", "image/jpeg"),
+            ("png", "image/png"),
+            ("gif", "image/gif"),
+            ("webp", "image/webp"),
+            ("tif", "image/tiff"),
+            ("tiff", "image/tiff"),
+            ("heic", "image/heic"),
+            ("heif", "image/heif"),
+            ("avif", "image/avif"),
+            ("cr2", "image/x-canon-cr2"),
+            ("cr3", "image/x-canon-cr3"),
+            ("nef", "image/x-nikon-nef"),
+            ("arw", "image/x-sony-arw"),
+            ("dng", "image/x-adobe-dng"),
+            ("raf", "image/x-fuji-raf"),
+            ("orf", "image/x-olympus-orf"),
+            ("rw2", "image/x-panasonic-rw2"),
+            ("pef", "image/x-pentax-pef"),
+            ("mp4", "video/mp4"),
+            ("mov", "video/quicktime"),
+            ("xmp", "application/rdf+xml"),
+        ] {
+            instance.extensions.insert(extension.into(), mime.into());
+        }
+
+        instance
+    }
+
     pub fn attribute() -> Attribute {
         "type/mime".into()
     }
+
+    /// Registers another matcher, which takes precedence over all previously registered ones
+    pub fn with_matcher(mut self, mime: impl Into<String>, matcher: Matcher) -> Self {
+        self.matchers.insert(0, (mime.into(), matcher));
+        self
+    }
+
+    /// Registers the MIME type to use for files with the given extension if none of the
+    /// matchers recognizes their contents
+    pub fn with_extension(mut self, extension: &str, mime: impl Into<String>) -> Self {
+        self.extensions
+            .insert(extension.to_ascii_lowercase(), mime.into());
+        self
+    }
+
+    /// Number of bytes read from the start of each blob
+    pub fn with_sniff_size(mut self, sniff_size: u64) -> Self {
+        self.sniff_size = sniff_size;
+        self
+    }
+
+    /// Infers the MIME type from the start of a file and its name
+    pub fn infer(&self, buf: &[u8], name: Option<&str>) -> Option<&str> {
+        if let Some((mime, _)) = self.matchers.iter().find(|(_, matcher)| matcher(buf)) {
+            return Some(mime);
+        }
+
+        if let Some(kind) = infer::get(buf) {
+            return Some(kind.mime_type());
+        }
+
+        let extension = Path::new(name?).extension()?.to_str()?.to_ascii_lowercase();
+        self.extensions.get(&extension).map(String::as_str)
+    }
+}
+
+impl Default for MimeInfer {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Extractor for MimeInfer {
+    fn name(&self) -> &str {
+        "mime"
+    }
+
     fn entry_added(
         &mut self,
         handle: &mut Handle,
@@ -33,28 +140,256 @@ impl Extractor for MimeInfer {
             return Ok(());
         }
 
-        let mut info = infer::Infer::new();
-        info.add("image/heic", "heic", custom_matcher::heic);
-
         // Fall back to reading the blob
-        let mut blob = handle.blob(entity)?;
-        let mut buf = [0; 32];
-        blob.read(&mut buf)?;
+        let blob = handle.blob(entity)?;
+        let mut buf = Vec::new();
+        blob.take(self.sniff_size).read_to_end(&mut buf)?;
+
+        // Ingested blobs are named after their hash, the original names are kept separately
+        let name = ["blob/path", "blob/name"]
+            .iter()
+            .flat_map(|attribute| handle.get(entity, &(*attribute).into()))
+            .find_map(|value| match value {
+                Value::Data(name) if Path::new(name).extension().is_some() => Some(name.as_str()),
+                _ => None,
+            });
 
-        let mime = info
-            .get(&buf)
-            .map(|m| m.mime_type())
-            .unwrap_or_else(|| "application/octet-stream".into());
+        let mime = self
+            .infer(&buf, name)
+            .unwrap_or("application/octet-stream")
+            .to_owned();
 
-        handle.insert(entity.clone(), mime_attribute, Value::Data(mime.into()));
+        handle.insert(entity.clone(), mime_attribute, Value::Data(mime));
 
         Ok(())
     }
 }
 
-mod custom_matcher {
+mod matchers {
+    /// Brands of an ISO-BMFF file, i.e. the major brand followed by the compatible ones
+    fn brands(buf: &[u8]) -> Vec<&[u8]> {
+        if buf.get(4..8) != Some(b"ftyp") {
+            return Vec::new();
+        }
+
+        let size = u32::from_be_bytes([buf[0], buf[1], buf[2], buf[3]]) as usize;
+        let Some(ftyp) = buf.get(8..size.min(buf.len())) else {
+            return Vec::new();
+        };
+
+        // The minor version sits between the major and the compatible brands
+        ftyp.chunks_exact(4)
+            .enumerate()
+            .filter(|(i, _)| *i != 1)
+            .map(|(_, brand)| brand)
+            .collect()
+    }
+
+    fn has_brand(buf: &[u8], wanted: &[&[u8; 4]]) -> bool {
+        brands(buf)
+            .iter()
+            .any(|brand| wanted.iter().any(|w| w == brand))
+    }
+
     pub fn heic(buf: &[u8]) -> bool {
-        const PATTERN: &[u8] = b"ftypheic";
-        return buf.len() >= PATTERN.len() && &buf[4..4 + PATTERN.len()] == PATTERN;
+        has_brand(buf, &[b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx"])
+    }
+
+    pub fn heif(buf: &[u8]) -> bool {
+        has_brand(buf, &[b"mif1", b"msf1"])
+    }
+
+    pub fn avif(buf: &[u8]) -> bool {
+        has_brand(buf, &[b"avif", b"avis"])
+    }
+
+    pub fn cr3(buf: &[u8]) -> bool {
+        brands(buf).first() == Some(&&b"crx "[..])
+    }
+
+    pub fn raf(buf: &[u8]) -> bool {
+        buf.starts_with(b"FUJIFILMCCD-RAW")
+    }
+
+    pub fn cr2(buf: &[u8]) -> bool {
+        tiff_byte_order(buf).is_some() && buf.get(8..10) == Some(b"CR")
+    }
+
+    pub fn dng(buf: &[u8]) -> bool {
+        // DNGVersion is mandatory and always stored in the first IFD
+        tiff_tags(buf).iter().any(|(tag, _)| *tag == 0xC612)
+    }
+
+    pub fn nef(buf: &[u8]) -> bool {
+        !dng(buf) && tiff_make(buf).is_some_and(|make| make.starts_with(b"NIKON"))
+    }
+
+    pub fn arw(buf: &[u8]) -> bool {
+        !dng(buf) && tiff_make(buf).is_some_and(|make| make.starts_with(b"SONY"))
+    }
+
+    /// Whether the buffer starts with a TIFF header and if so, whether it is little endian
+    fn tiff_byte_order(buf: &[u8]) -> Option<bool> {
+        match buf.get(0..4)? {
+            b"II*\0" => Some(true),
+            b"MM\0*" => Some(false),
+            _ => None,
+        }
+    }
+
+    /// Tags of the first IFD along with their raw entries
+    fn tiff_tags(buf: &[u8]) -> Vec<(u16, &[u8])> {
+        let Some(little_endian) = tiff_byte_order(buf) else {
+            return Vec::new();
+        };
+
+        let u16_at = |offset: usize| {
+            let bytes = [*buf.get(offset)?, *buf.get(offset + 1)?];
+            Some(if little_endian {
+                u16::from_le_bytes(bytes)
+            } else {
+                u16::from_be_bytes(bytes)
+            })
+        };
+
+        let Some(ifd) = buf.get(4..8).map(|bytes| {
+            let bytes = [bytes[0], bytes[1], bytes[2], bytes[3]];
+            if little_endian {
+                u32::from_le_bytes(bytes)
+            } else {
+                u32::from_be_bytes(bytes)
+            }
+        }) else {
+            return Vec::new();
+        };
+
+        let ifd = ifd as usize;
+        let count = u16_at(ifd).unwrap_or(0) as usize;
+
+        (0..count)
+            .filter_map(|i| {
+                let entry = ifd + 2 + i * 12;
+                Some((u16_at(entry)?, buf.get(entry..entry + 12)?))
+            })
+            .collect()
+    }
+
+    /// Value of the `Make` tag, if it is within the buffer
+    fn tiff_make(buf: &[u8]) -> Option<&[u8]> {
+        let little_endian = tiff_byte_order(buf)?;
+        let (_, entry) = tiff_tags(buf).into_iter().find(|(tag, _)| *tag == 0x010F)?;
+
+        let bytes = [entry[4], entry[5], entry[6], entry[7]];
+        let count = if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        } as usize;
+
+        // Short strings are stored inline, longer ones at the given offset
+        if count <= 4 {
+            return Some(&entry[8..8 + count]);
+        }
+
+        let bytes = [entry[8], entry[9], entry[10], entry[11]];
+        let offset = if little_endian {
+            u32::from_le_bytes(bytes)
+        } else {
+            u32::from_be_bytes(bytes)
+        } as usize;
+
+        buf.get(offset..offset + count)
+    }
+}
+
+#[cfg(test)]
+mod does {
+    use super::*;
+
+    fn ftyp(major: &[u8; 4], compatible: &[&[u8; 4]]) -> Vec<u8> {
+        let mut buf = ((16 + 4 * compatible.len()) as u32).to_be_bytes().to_vec();
+        buf.extend(b"ftyp");
+        buf.extend(major);
+        buf.extend([0; 4]);
+        compatible.iter().for_each(|brand| buf.extend(*brand));
+        buf
+    }
+
+    fn tiff(tags: &[(u16, &[u8])]) -> Vec<u8> {
+        let data_offset = 8 + 2 + tags.len() * 12 + 4;
+        let mut buf = b"II*\0\x08\0\0\0".to_vec();
+        let mut data: Vec<u8> = Vec::new();
+
+        buf.extend((tags.len() as u16).to_le_bytes());
+        for (tag, value) in tags {
+            buf.extend(tag.to_le_bytes());
+            buf.extend(2u16.to_le_bytes());
+            buf.extend((value.len() as u32).to_le_bytes());
+            buf.extend(((data_offset + data.len()) as u32).to_le_bytes());
+            data.extend(*value);
+        }
+        buf.extend([0; 4]);
+        buf.extend(data);
+        buf
+    }
+
+    #[test]
+    fn detect_heif_variants() {
+        let mime = MimeInfer::new();
+
+        let cases = [
+            (ftyp(b"heic", &[b"mif1", b"heic"]), "image/heic"),
+            (ftyp(b"mif1", &[b"mif1", b"heix"]), "image/heic"),
+            (ftyp(b"mif1", &[b"mif1", b"miaf"]), "image/heif"),
+            (ftyp(b"avif", &[b"avif", b"mif1", b"miaf"]), "image/avif"),
+            (ftyp(b"mif1", &[b"avif", b"mif1"]), "image/avif"),
+            (ftyp(b"crx ", &[b"crx ", b"isom"]), "image/x-canon-cr3"),
+        ];
+
+        for (buf, expected) in cases {
+            assert_eq!(mime.infer(&buf, None), Some(expected));
+        }
+    }
+
+    #[test]
+    fn detect_tiff_based_raw_files() {
+        let mime = MimeInfer::new();
+
+        let mut cr2 = tiff(&[(0x010F, b"Canon\0")]);
+        cr2[8..10].copy_from_slice(b"CR");
+
+        let cases = [
+            (cr2, "image/x-canon-cr2"),
+            (
+                tiff(&[(0x010F, b"NIKON CORPORATION\0")]),
+                "image/x-nikon-nef",
+            ),
+            (tiff(&[(0x010F, b"SONY\0")]), "image/x-sony-arw"),
+            (
+                tiff(&[(0x010F, b"SONY\0"), (0xC612, b"\x01\x04\0\0")]),
+                "image/x-adobe-dng",
+            ),
+            (tiff(&[(0x010F, b"Acme\0")]), "image/tiff"),
+        ];
+
+        for (buf, expected) in cases {
+            assert_eq!(mime.infer(&buf, None), Some(expected));
+        }
+    }
+
+    #[test]
+    fn fall_back_to_extensions() {
+        let mime = MimeInfer::new().with_extension("ORI", "image/x-olympus-ori");
+
+        assert_eq!(
+            mime.infer(b"garbage", Some("P1010001.RW2")),
+            Some("image/x-panasonic-rw2")
+        );
+        assert_eq!(
+            mime.infer(b"garbage", Some("dir/a.ori")),
+            Some("image/x-olympus-ori")
+        );
+        assert_eq!(mime.infer(b"garbage", Some("unknown.bin")), None);
+        assert_eq!(mime.infer(b"garbage", None), None);
     }
 }
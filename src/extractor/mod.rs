@@ -6,15 +6,33 @@ use std::error::Error;
 
 mod blob;
 mod exif;
+mod failure;
 mod geonames;
 mod mime;
+mod similarity;
+mod thumbnail;
+mod video;
+mod xmp;
 
-pub use blob::BlobLoader;
+pub use blob::{BlobChange, BlobLoader, ScanSummary};
 pub use exif::ExifExtractor;
-pub use geonames::GeoNames;
-pub use mime::MimeInfer;
+pub use failure::{Failure, FailureKind, FailureReport, RetryPolicy};
+pub use geonames::{FeatureClass, GeoNames, GeoNamesDumps};
+pub use mime::{Matcher, MimeInfer};
+pub use similarity::{
+    hamming_distance, HashAlgorithm, HashIndex, PerceptualHasher, SimilarityLinker,
+};
+pub use thumbnail::ThumbnailExtractor;
+pub use video::{VideoData, VideoExtractor};
+pub use xmp::{Region, XmpData, XmpExtractor};
 
 pub trait Extractor {
+    /// Short name used when reporting failures, defaults to the name of the type
+    fn name(&self) -> &str {
+        let name = std::any::type_name::<Self>();
+        name.rsplit("::").next().unwrap_or(name)
+    }
+
     #[allow(unused_variables)]
     fn init(&mut self, handle: &mut Handle) -> Result<(), Box<dyn Error>> {
         Ok(())
@@ -30,11 +48,27 @@ pub trait Extractor {
     ) -> Result<(), Box<dyn Error>> {
         Ok(())
     }
+
+    /// Whether the extractor receives input from outside the write log (e.g. a file watcher),
+    /// which keeps the pipeline running even when there is nothing left to process.
+    fn is_live(&self) -> bool {
+        false
+    }
+
+    /// Called periodically while the write log is idle and at least one extractor is live
+    #[allow(unused_variables)]
+    fn poll(&mut self, handle: &mut Handle) -> Result<(), Box<dyn Error>> {
+        Ok(())
+    }
 }
 
 pub struct Logger;
 
 impl Extractor for Logger {
+    fn name(&self) -> &str {
+        "log"
+    }
+
     fn entry_added(
         &mut self,
         _handle: &mut Handle,
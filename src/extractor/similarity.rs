@@ -0,0 +1,555 @@
+use super::{thumbnail::decode_image, Extractor, MimeInfer};
+use crate::{
+    db::{Attribute, Entity, Rule, Value, Variable, VariableSetExt},
+    handle::Handle,
+    query,
+};
+use image::{imageops::FilterType, DynamicImage};
+use std::{collections::HashMap, error::Error, f64::consts::PI};
+
+/// Perceptual hash algorithms, all of them produce 64 bit hashes which are compared using
+/// their hamming distance
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    /// Compares each pixel to the mean brightness, fast but sensitive to gamma changes
+    Average,
+    /// Compares neighboring pixels, robust against brightness and contrast changes
+    Difference,
+    /// Compares the low frequencies of a discrete cosine transform, the most robust one
+    Dct,
+}
+
+impl HashAlgorithm {
+    /// Attribute the hashes are stored in, as hex strings
+    pub fn attribute(&self) -> Attribute {
+        match self {
+            Self::Average => "image/ahash".into(),
+            Self::Difference => "image/dhash".into(),
+            Self::Dct => "image/phash".into(),
+        }
+    }
+
+    pub fn hash(&self, image: &DynamicImage) -> u64 {
+        match self {
+            Self::Average => {
+                let pixels = grayscale(image, 8, 8);
+                let mean = pixels.iter().sum::<f64>() / pixels.len() as f64;
+                bits(pixels.iter().map(|&pixel| pixel > mean))
+            }
+            Self::Difference => {
+                let pixels = grayscale(image, 9, 8);
+                bits((0..64).map(|i| {
+                    let (row, column) = (i / 8, i % 8);
+                    pixels[row * 9 + column] < pixels[row * 9 + column + 1]
+                }))
+            }
+            Self::Dct => {
+                let pixels = grayscale(image, 32, 32);
+                let coefficients = dct_low_frequencies(&pixels, 32, 8);
+
+                // The DC coefficient only represents the average brightness
+                let mut sorted = coefficients[1..].to_vec();
+                sorted.sort_by(f64::total_cmp);
+                let median = sorted[sorted.len() / 2];
+
+                bits(coefficients.iter().map(|&c| c > median))
+            }
+        }
+    }
+}
+
+fn grayscale(image: &DynamicImage, width: u32, height: u32) -> Vec<f64> {
+    image
+        .resize_exact(width, height, FilterType::Triangle)
+        .to_luma8()
+        .pixels()
+        .map(|pixel| f64::from(pixel.0[0]))
+        .collect()
+}
+
+fn bits(bits: impl Iterator<Item = bool>) -> u64 {
+    bits.fold(0, |hash, bit| (hash << 1) | u64::from(bit))
+}
+
+/// Two dimensional DCT-II of a square image, only computing the top left `keep`×`keep`
+/// coefficients
+fn dct_low_frequencies(pixels: &[f64], size: usize, keep: usize) -> Vec<f64> {
+    let basis = |frequency: usize, position: usize| {
+        ((2 * position + 1) as f64 * frequency as f64 * PI / (2 * size) as f64).cos()
+    };
+
+    // Rows first, then columns of the intermediate result
+    let mut rows = vec![0.0; size * keep];
+    for y in 0..size {
+        for u in 0..keep {
+            rows[y * keep + u] = (0..size).map(|x| pixels[y * size + x] * basis(u, x)).sum();
+        }
+    }
+
+    let mut coefficients = vec![0.0; keep * keep];
+    for v in 0..keep {
+        for u in 0..keep {
+            coefficients[v * keep + u] = (0..size).map(|y| rows[y * keep + u] * basis(v, y)).sum();
+        }
+    }
+
+    coefficients
+}
+
+fn parse(value: &Value) -> Option<u64> {
+    match value {
+        Value::Data(hex) => u64::from_str_radix(hex, 16).ok(),
+        Value::Reference(_) => None,
+    }
+}
+
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+/// Computes a perceptual hash for every image
+pub struct PerceptualHasher {
+    algorithm: HashAlgorithm,
+}
+
+impl PerceptualHasher {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        Self { algorithm }
+    }
+
+    fn hash_image(&self, handle: &mut Handle, entity: &Entity) -> Result<(), Box<dyn Error>> {
+        let attribute = self.algorithm.attribute();
+
+        if handle.get(entity, &attribute).next().is_some() {
+            return Ok(());
+        }
+
+        let image = decode_image(handle.blob(entity)?)?;
+        let hash = self.algorithm.hash(&image);
+        handle.insert(entity.clone(), attribute, format!("{hash:016x}"));
+
+        Ok(())
+    }
+}
+
+impl Default for PerceptualHasher {
+    fn default() -> Self {
+        Self::new(HashAlgorithm::Difference)
+    }
+}
+
+impl Extractor for PerceptualHasher {
+    fn name(&self) -> &str {
+        "phash"
+    }
+
+    fn entry_added(
+        &mut self,
+        handle: &mut Handle,
+        entity: &Entity,
+        attribute: &Attribute,
+        value: &Value,
+    ) -> Result<(), Box<dyn Error>> {
+        if attribute == &MimeInfer::attribute() {
+            match value {
+                Value::Data(mime) if mime.starts_with("image") => {
+                    self.hash_image(handle, entity)?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// BK-tree over perceptual hashes, allows finding all hashes within a hamming distance
+/// without comparing against every single one
+#[derive(Debug, Default)]
+pub struct HashIndex {
+    nodes: Vec<Node>,
+}
+
+#[derive(Debug)]
+struct Node {
+    hash: u64,
+    entities: Vec<Entity>,
+    children: HashMap<u32, usize>,
+}
+
+impl HashIndex {
+    pub fn insert(&mut self, hash: u64, entity: Entity) {
+        if self.nodes.is_empty() {
+            self.nodes.push(Node::new(hash, entity));
+            return;
+        }
+
+        let mut current = 0;
+
+        loop {
+            let distance = hamming_distance(self.nodes[current].hash, hash);
+
+            if distance == 0 {
+                if !self.nodes[current].entities.contains(&entity) {
+                    self.nodes[current].entities.push(entity);
+                }
+                return;
+            }
+
+            match self.nodes[current].children.get(&distance) {
+                Some(&child) => current = child,
+                None => {
+                    let index = self.nodes.len();
+                    self.nodes.push(Node::new(hash, entity));
+                    self.nodes[current].children.insert(distance, index);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// All entities whose hash is at most `max_distance` bits away, along with their distance
+    pub fn find(&self, hash: u64, max_distance: u32) -> Vec<(&Entity, u32)> {
+        let mut found = Vec::new();
+        let mut pending = if self.nodes.is_empty() {
+            vec![]
+        } else {
+            vec![0]
+        };
+
+        while let Some(index) = pending.pop() {
+            let node = &self.nodes[index];
+            let distance = hamming_distance(node.hash, hash);
+
+            if distance <= max_distance {
+                found.extend(node.entities.iter().map(|entity| (entity, distance)));
+            }
+
+            // Thanks to the triangle inequality only these subtrees can contain matches
+            let range = distance.saturating_sub(max_distance)..=distance + max_distance;
+            pending.extend(
+                node.children
+                    .iter()
+                    .filter(|(d, _)| range.contains(d))
+                    .map(|(_, &child)| child),
+            );
+        }
+
+        found
+    }
+}
+
+impl Node {
+    fn new(hash: u64, entity: Entity) -> Self {
+        Self {
+            hash,
+            entities: vec![entity],
+            children: HashMap::new(),
+        }
+    }
+}
+
+/// Links images with similar perceptual hashes.
+///
+/// Every pair gets `image/similar` references in both directions plus a `similarity:<a>:<b>`
+/// entity per direction holding the hamming distance, so near-duplicates can be queried like:
+///
+/// ```text
+/// { #image, :"image/similar", #other },
+/// { #similarity, :"similarity/source", #image },
+/// { #similarity, :"similarity/target", #other },
+/// { #similarity, :"similarity/distance", ?distance }
+/// ```
+pub struct SimilarityLinker {
+    algorithm: HashAlgorithm,
+    max_distance: u32,
+    index: HashIndex,
+}
+
+impl SimilarityLinker {
+    pub fn new(algorithm: HashAlgorithm) -> Self {
+        Self {
+            algorithm,
+            max_distance: 10,
+            index: HashIndex::default(),
+        }
+    }
+
+    /// Maximum number of differing bits for two images to count as similar
+    pub fn with_max_distance(mut self, max_distance: u32) -> Self {
+        self.max_distance = max_distance;
+        self
+    }
+
+    fn link(&mut self, handle: &mut Handle, entity: &Entity, hash: u64) {
+        let attribute = self.algorithm.attribute();
+
+        // The hash changes along with the image, so links found before may not hold anymore
+        Self::unlink(handle, entity);
+
+        let candidates: Vec<(Entity, u32)> = self
+            .index
+            .find(hash, self.max_distance)
+            .into_iter()
+            .filter(|(other, _)| *other != entity)
+            .map(|(other, distance)| (other.clone(), distance))
+            .collect();
+
+        for (other, distance) in candidates {
+            // The index is never cleaned up, so skip blobs which are gone or changed since
+            let current = handle.get(&other, &attribute).find_map(parse);
+            if current.map(|h| hamming_distance(h, hash)) != Some(distance) {
+                continue;
+            }
+
+            for (source, target) in [(entity, &other), (&other, entity)] {
+                let similarity = Entity::from(format!("similarity:{}:{}", source.0, target.0));
+
+                handle.insert(similarity.clone(), "similarity/source", source);
+                handle.insert(similarity.clone(), "similarity/target", target);
+                handle.insert(similarity, "similarity/distance", distance);
+                handle.insert(source.clone(), "image/similar", target);
+            }
+        }
+
+        self.index.insert(hash, entity.clone());
+    }
+
+    /// Retracts the similarity entities of an image along with the links of other images to it
+    pub(super) fn unlink(handle: &mut Handle, entity: &Entity) {
+        let mut similarities = Vec::new();
+
+        for attribute in ["similarity/source", "similarity/target"] {
+            similarities.extend(
+                handle
+                    .ave
+                    .values(&attribute.into(), &Value::from(entity))
+                    .cloned(),
+            );
+        }
+
+        for similarity in similarities {
+            handle.retract_entity(&similarity);
+        }
+
+        let others: Vec<_> = handle
+            .ave
+            .values(&"image/similar".into(), &Value::from(entity))
+            .cloned()
+            .collect();
+
+        for other in others {
+            handle.retract(&other, &"image/similar".into(), &Value::from(entity));
+        }
+
+        handle.retract_attribute(entity, &"image/similar".into());
+    }
+}
+
+impl Extractor for SimilarityLinker {
+    fn name(&self) -> &str {
+        "similarity"
+    }
+
+    /// Fills the index with the hashes which are already in the database
+    fn init(&mut self, handle: &mut Handle) -> Result<(), Box<dyn Error>> {
+        let attribute = self.algorithm.attribute();
+
+        query!(handle where (#image, ?hash) match [
+            { #image, :attribute, ?hash }
+        ] => hashes);
+
+        for set in hashes.iter() {
+            if let (Some(image), Some(hash)) = (set.get(&image), set.get(&hash).and_then(parse)) {
+                self.index.insert(hash, image.clone());
+            }
+        }
+
+        Ok(())
+    }
+
+    fn entry_added(
+        &mut self,
+        handle: &mut Handle,
+        entity: &Entity,
+        attribute: &Attribute,
+        value: &Value,
+    ) -> Result<(), Box<dyn Error>> {
+        if attribute == &self.algorithm.attribute() {
+            if let Some(hash) = parse(value) {
+                self.link(handle, entity, hash);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod does {
+    use super::*;
+    use crate::db::Database;
+    use image::{ImageBuffer, ImageFormat, Luma};
+
+    fn scene(brightness: f64) -> DynamicImage {
+        DynamicImage::ImageLuma8(ImageBuffer::from_fn(64, 64, |x, y| {
+            let (x, y) = (f64::from(x), f64::from(y));
+            let value = 100.0 + 50.0 * (x * 0.2).sin() * (y * 0.15).cos() + 30.0 * (x + y).sin();
+            Luma([(value + brightness) as u8])
+        }))
+    }
+
+    fn stripes() -> DynamicImage {
+        DynamicImage::ImageLuma8(ImageBuffer::from_fn(64, 64, |x, y| {
+            let value = 128.0 + 100.0 * (f64::from(x) * 0.3 + f64::from(y) * 0.05).cos();
+            Luma([value as u8])
+        }))
+    }
+
+    #[test]
+    fn find_hashes_within_distance() {
+        let mut index = HashIndex::default();
+        index.insert(0b0000, "a".into());
+        index.insert(0b0001, "b".into());
+        index.insert(0b0111, "c".into());
+        index.insert(u64::MAX, "d".into());
+
+        let mut found: Vec<_> = index
+            .find(0b0011, 1)
+            .into_iter()
+            .map(|(entity, distance)| (entity.0.clone(), distance))
+            .collect();
+        found.sort();
+
+        assert_eq!(found, [("b".into(), 1), ("c".into(), 1)]);
+    }
+
+    #[test]
+    fn hash_similar_images_alike() {
+        for algorithm in [
+            HashAlgorithm::Average,
+            HashAlgorithm::Difference,
+            HashAlgorithm::Dct,
+        ] {
+            let original = algorithm.hash(&scene(0.0));
+            let brighter = algorithm.hash(&scene(20.0));
+            let different = algorithm.hash(&stripes());
+
+            assert!(hamming_distance(original, brighter) <= 4, "{algorithm:?}");
+            assert!(hamming_distance(original, different) > 10, "{algorithm:?}");
+        }
+    }
+
+    #[test]
+    fn link_near_duplicates() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let (db, write_log) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+        let mut extractors = crate::make_extractors![
+            PerceptualHasher::default(),
+            SimilarityLinker::new(HashAlgorithm::Difference)
+        ];
+
+        let images = [
+            ("original.png", scene(0.0)),
+            ("burst.png", scene(20.0)),
+            ("other.png", stripes()),
+        ];
+
+        for (name, image) in images {
+            image.save_with_format(dir.path().join(name), ImageFormat::Png)?;
+            handle.insert(Entity::from(name), "type/mime", "image/png");
+        }
+
+        crate::process_write_log(
+            &write_log,
+            &mut handle,
+            &mut extractors,
+            &Default::default(),
+        )?;
+
+        let similar = |name: &str| -> Vec<Value> {
+            handle
+                .get(&Entity::from(name), &"image/similar".into())
+                .cloned()
+                .collect()
+        };
+
+        assert_eq!(
+            similar("original.png"),
+            [Value::from(&Entity::from("burst.png"))]
+        );
+        assert_eq!(
+            similar("burst.png"),
+            [Value::from(&Entity::from("original.png"))]
+        );
+        assert!(similar("other.png").is_empty());
+
+        let similarity = Entity::from("similarity:burst.png:original.png");
+        assert!(handle
+            .get(&similarity, &"similarity/distance".into())
+            .next()
+            .is_some());
+
+        Ok(())
+    }
+
+    #[test]
+    fn relink_modified_and_removed_images() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let (db, write_log) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+        let mut loader = crate::extractor::BlobLoader::new();
+        let mut extractors = crate::make_extractors![
+            MimeInfer::new(),
+            PerceptualHasher::default(),
+            SimilarityLinker::new(HashAlgorithm::Difference)
+        ];
+
+        let mut update = |handle: &mut Handle| -> Result<(), Box<dyn Error>> {
+            loader.scan(handle)?;
+            crate::process_write_log(&write_log, handle, &mut extractors, &Default::default())?;
+            Ok(())
+        };
+
+        let similar = |handle: &Handle, name: &str| -> Vec<Value> {
+            handle
+                .get(&Entity::from(name), &"image/similar".into())
+                .cloned()
+                .collect()
+        };
+
+        scene(0.0).save_with_format(dir.path().join("original.png"), ImageFormat::Png)?;
+        scene(20.0).save_with_format(dir.path().join("burst.png"), ImageFormat::Png)?;
+        stripes().save_with_format(dir.path().join("other.png"), ImageFormat::Png)?;
+        update(&mut handle)?;
+        assert_eq!(
+            similar(&handle, "original.png"),
+            [Value::from(&Entity::from("burst.png"))]
+        );
+
+        stripes().save_with_format(dir.path().join("burst.png"), ImageFormat::Png)?;
+        update(&mut handle)?;
+
+        assert!(similar(&handle, "original.png").is_empty());
+        assert_eq!(
+            similar(&handle, "burst.png"),
+            [Value::from(&Entity::from("other.png"))]
+        );
+        assert_eq!(
+            handle
+                .eav
+                .get(&Entity::from("similarity:original.png:burst.png"))
+                .count(),
+            0
+        );
+
+        std::fs::remove_file(dir.path().join("other.png"))?;
+        update(&mut handle)?;
+
+        assert!(similar(&handle, "burst.png").is_empty());
+        assert_eq!(handle.ave.get(&"similarity/target".into()).count(), 0);
+
+        Ok(())
+    }
+}
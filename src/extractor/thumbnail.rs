@@ -0,0 +1,205 @@
+use super::{Extractor, MimeInfer};
+use crate::{
+    db::{Attribute, Entity, Value},
+    handle::Handle,
+};
+use exif::{In, Tag};
+use image::{
+    codecs::jpeg::JpegEncoder, metadata::Orientation, DynamicImage, ImageFormat, ImageReader,
+};
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{BufRead, BufWriter, Seek, SeekFrom, Write},
+};
+
+/// Writes resized JPEG previews of images into the derived blob area of the storage
+pub struct ThumbnailExtractor {
+    size: u32,
+    quality: u8,
+}
+
+impl Default for ThumbnailExtractor {
+    fn default() -> Self {
+        Self {
+            size: 256,
+            quality: 80,
+        }
+    }
+}
+
+impl ThumbnailExtractor {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Maximum width and height of the thumbnails, the aspect ratio is preserved
+    pub fn with_size(mut self, size: u32) -> Self {
+        self.size = size;
+        self
+    }
+
+    pub fn with_quality(mut self, quality: u8) -> Self {
+        self.quality = quality;
+        self
+    }
+
+    /// Decodes an image and scales it down, see [`decode_image`] for the supported formats
+    pub fn render(&self, reader: impl BufRead + Seek) -> Result<DynamicImage, Box<dyn Error>> {
+        Ok(decode_image(reader)?.thumbnail(self.size, self.size))
+    }
+
+    fn create_thumbnail(&self, handle: &mut Handle, entity: &Entity) -> Result<(), Box<dyn Error>> {
+        // Don't do the work twice
+        if handle
+            .get(entity, &"image/thumbnail".into())
+            .next()
+            .is_some()
+        {
+            return Ok(());
+        }
+
+        let thumbnail = self.render(handle.blob(entity)?)?;
+        let (derived, path) = handle.derived_blob(entity, "thumbnail", "jpg");
+
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut writer = BufWriter::new(File::create(&path)?);
+        thumbnail
+            .to_rgb8()
+            .write_with_encoder(JpegEncoder::new_with_quality(&mut writer, self.quality))?;
+        writer.flush()?;
+
+        // No `blob/size` on purpose, derived blobs should not be processed by other extractors
+        let root = handle.primary_root().name.clone();
+        let relative = path.strip_prefix(&handle.primary_root().path)?;
+        handle.insert(derived.clone(), "blob/root", root);
+        handle.insert(derived.clone(), "blob/path", relative.to_string_lossy());
+        handle.insert(derived.clone(), "blob/source", entity);
+        handle.insert(derived.clone(), "image/width", thumbnail.width());
+        handle.insert(derived.clone(), "image/height", thumbnail.height());
+        handle.insert(entity.clone(), "image/thumbnail", derived);
+
+        Ok(())
+    }
+}
+
+/// Decodes a JPEG or PNG image and rotates it upright, falling back to the thumbnail embedded
+/// in the EXIF data for formats which can't be decoded (e.g. HEIC)
+pub(super) fn decode_image(
+    mut reader: impl BufRead + Seek,
+) -> Result<DynamicImage, Box<dyn Error>> {
+    let exif = exif::Reader::new().read_from_container(&mut reader).ok();
+    reader.seek(SeekFrom::Start(0))?;
+
+    let decoder = ImageReader::new(&mut reader).with_guessed_format()?;
+    let mut image = match decoder.format() {
+        Some(ImageFormat::Jpeg | ImageFormat::Png) => decoder.decode()?,
+        _ => {
+            let embedded = exif
+                .as_ref()
+                .and_then(embedded_thumbnail)
+                .ok_or(exif::Error::NotFound("thumbnail"))?;
+            image::load_from_memory_with_format(embedded, ImageFormat::Jpeg)?
+        }
+    };
+
+    if let Some(orientation) = exif
+        .as_ref()
+        .and_then(|exif| exif.get_field(Tag::Orientation, In::PRIMARY))
+        .and_then(|field| field.value.get_uint(0))
+        .and_then(|orientation| Orientation::from_exif(orientation as u8))
+    {
+        image.apply_orientation(orientation);
+    }
+
+    Ok(image)
+}
+
+fn embedded_thumbnail(exif: &exif::Exif) -> Option<&[u8]> {
+    let offset = exif
+        .get_field(Tag::JPEGInterchangeFormat, In::THUMBNAIL)?
+        .value
+        .get_uint(0)? as usize;
+    let length = exif
+        .get_field(Tag::JPEGInterchangeFormatLength, In::THUMBNAIL)?
+        .value
+        .get_uint(0)? as usize;
+
+    exif.buf().get(offset..offset + length)
+}
+
+impl Extractor for ThumbnailExtractor {
+    fn name(&self) -> &str {
+        "thumbnail"
+    }
+
+    fn entry_added(
+        &mut self,
+        handle: &mut Handle,
+        entity: &Entity,
+        attribute: &Attribute,
+        value: &Value,
+    ) -> Result<(), Box<dyn Error>> {
+        if attribute == &MimeInfer::attribute() {
+            match value {
+                Value::Data(mime) if mime.starts_with("image") => {
+                    self.create_thumbnail(handle, entity)?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod does {
+    use super::*;
+    use crate::db::Database;
+    use image::{ImageBuffer, Rgb};
+    use std::io::Cursor;
+
+    #[test]
+    fn write_thumbnails_into_derived_area() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let (db, write_log) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+        let mut extractors = crate::make_extractors![ThumbnailExtractor::new().with_size(64)];
+
+        let mut png = Cursor::new(Vec::new());
+        ImageBuffer::from_pixel(400, 200, Rgb([200u8, 30, 30]))
+            .write_to(&mut png, ImageFormat::Png)?;
+        fs::write(dir.path().join("red.png"), png.into_inner())?;
+
+        let entity = Entity::from("red.png");
+        handle.insert(entity.clone(), "type/mime", "image/png");
+
+        crate::process_write_log(
+            &write_log,
+            &mut handle,
+            &mut extractors,
+            &Default::default(),
+        )?;
+
+        let thumbnail = match handle.get(&entity, &"image/thumbnail".into()).next() {
+            Some(Value::Reference(thumbnail)) => thumbnail.clone(),
+            other => panic!("unexpected thumbnail {other:?}"),
+        };
+
+        let path = handle.blob_path(&thumbnail)?;
+        assert!(path.starts_with(dir.path().join(crate::handle::DERIVED_DIR)));
+
+        let decoded = image::open(path)?;
+        assert_eq!((decoded.width(), decoded.height()), (64, 32));
+        assert_eq!(
+            handle.get(&thumbnail, &"image/width".into()).next(),
+            Some(&"64".into())
+        );
+
+        Ok(())
+    }
+}
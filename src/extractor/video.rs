@@ -0,0 +1,501 @@
+use super::{Extractor, MimeInfer};
+use crate::{
+    db::{Attribute, Entity, Value},
+    handle::Handle,
+};
+use std::{
+    error::Error,
+    io::{self, Read, Seek, SeekFrom},
+};
+use time::{format_description::well_known::Rfc3339, Duration, OffsetDateTime, UtcOffset};
+
+/// Seconds between 1904-01-01, which QuickTime uses as its epoch, and the Unix epoch
+const QUICKTIME_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// Movie headers larger than this are most likely garbage and not worth reading
+const MAX_MOOV_SIZE: u64 = 64 * 1024 * 1024;
+
+/// Reads duration, resolution, creation time and location from the atoms of ISO-BMFF based
+/// containers, which includes MP4, MOV and most phone videos
+pub struct VideoExtractor;
+
+#[derive(Debug, Default, PartialEq)]
+pub struct VideoData {
+    /// Duration in seconds
+    pub duration: Option<f64>,
+    pub width: Option<u32>,
+    pub height: Option<u32>,
+    pub creation: Option<OffsetDateTime>,
+    pub lat: Option<f64>,
+    pub lng: Option<f64>,
+    pub alt: Option<f64>,
+}
+
+impl VideoExtractor {
+    pub fn extract<T: Read + Seek>(reader: &mut T) -> Result<VideoData, Box<dyn Error>> {
+        let moov = read_moov(reader)?;
+        let mut data = VideoData::default();
+
+        for (kind, content) in atoms(&moov) {
+            match &kind {
+                b"mvhd" => parse_mvhd(content, &mut data),
+                b"trak" if data.width.is_none() => parse_trak(content, &mut data),
+                b"udta" => parse_udta(content, &mut data),
+                b"meta" => parse_meta(content, &mut data),
+                _ => {}
+            }
+        }
+
+        Ok(data)
+    }
+
+    fn extract_video(&self, handle: &mut Handle, entity: &Entity) -> Result<(), Box<dyn Error>> {
+        let mut blob = handle.blob(entity)?;
+        let data = Self::extract(&mut blob)?;
+
+        if let Some(duration) = data.duration {
+            handle.insert(entity.clone(), "video/duration", duration);
+        }
+
+        if let Some(width) = data.width {
+            handle.insert(entity.clone(), "image/width", width);
+        }
+
+        if let Some(height) = data.height {
+            handle.insert(entity.clone(), "image/height", height);
+        }
+
+        if let Some(creation) = data.creation {
+            handle.insert(entity.clone(), "time/creation", creation.format(&Rfc3339)?);
+        }
+
+        if let (Some(lat), Some(lng)) = (data.lat, data.lng) {
+            handle.insert(entity.clone(), "location/latitude", lat);
+            handle.insert(entity.clone(), "location/longitude", lng);
+        }
+
+        if let Some(alt) = data.alt {
+            handle.insert(entity.clone(), "location/altitude", alt);
+        }
+
+        Ok(())
+    }
+}
+
+/// Finds the top level `moov` atom and reads it into memory, skipping over everything else
+/// as the media data can be huge
+fn read_moov<T: Read + Seek>(reader: &mut T) -> Result<Vec<u8>, io::Error> {
+    let length = reader.seek(SeekFrom::End(0))?;
+    let mut position = reader.seek(SeekFrom::Start(0))?;
+
+    while position + 8 <= length {
+        let mut header = [0; 8];
+        reader.read_exact(&mut header)?;
+
+        let mut size = u64::from(u32::from_be_bytes([
+            header[0], header[1], header[2], header[3],
+        ]));
+        let mut header_size = 8;
+
+        if size == 1 {
+            let mut large = [0; 8];
+            reader.read_exact(&mut large)?;
+            size = u64::from_be_bytes(large);
+            header_size = 16;
+        } else if size == 0 {
+            size = length - position;
+        }
+
+        // Sizes are untrusted, anything reaching past the end means the file is malformed
+        let end = match position.checked_add(size) {
+            Some(end) if size >= header_size && end <= length => end,
+            _ => break,
+        };
+
+        if &header[4..8] == b"moov" {
+            if size > MAX_MOOV_SIZE {
+                break;
+            }
+
+            let mut moov = vec![0; (size - header_size) as usize];
+            reader.read_exact(&mut moov)?;
+            return Ok(moov);
+        }
+
+        position = reader.seek(SeekFrom::Start(end))?;
+    }
+
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "no movie header found",
+    ))
+}
+
+/// Splits the contents of an atom into its children
+fn atoms(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut atoms = Vec::new();
+
+    while let [a, b, c, d, e, f, g, h, ..] = *data {
+        let mut size = u32::from_be_bytes([a, b, c, d]) as usize;
+        let mut header_size = 8;
+
+        if size == 1 {
+            let Some(large) = data.get(8..16) else {
+                break;
+            };
+            let Some(large) = read_u64(large, 0).and_then(|size| usize::try_from(size).ok()) else {
+                break;
+            };
+            size = large;
+            header_size = 16;
+        } else if size == 0 {
+            size = data.len();
+        }
+
+        let Some(content) = data.get(header_size..size) else {
+            break;
+        };
+
+        atoms.push(([e, f, g, h], content));
+        data = &data[size..];
+    }
+
+    atoms
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_be_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// Movie header, contains the creation time (in UTC) and the overall duration
+fn parse_mvhd(content: &[u8], data: &mut VideoData) {
+    let values = match content.first() {
+        Some(1) => read_u64(content, 4).zip(read_u32(content, 20).zip(read_u64(content, 24))),
+        _ => read_u32(content, 4)
+            .map(u64::from)
+            .zip(read_u32(content, 12).zip(read_u32(content, 16).map(u64::from))),
+    };
+
+    let Some((creation, (timescale, duration))) = values else {
+        return;
+    };
+
+    // Zero is used when the creation time is unknown
+    if creation != 0 {
+        data.creation = i64::try_from(creation)
+            .ok()
+            .and_then(|creation| creation.checked_sub(QUICKTIME_EPOCH_OFFSET))
+            .and_then(|seconds| OffsetDateTime::UNIX_EPOCH.checked_add(Duration::seconds(seconds)));
+    }
+
+    if timescale != 0 && duration != u64::MAX && duration != u64::from(u32::MAX) {
+        data.duration = Some(duration as f64 / f64::from(timescale));
+    }
+}
+
+/// Track, only the dimensions of the first video track are of interest
+fn parse_trak(content: &[u8], data: &mut VideoData) {
+    for (kind, tkhd) in atoms(content) {
+        if &kind != b"tkhd" {
+            continue;
+        }
+
+        // The dimensions are stored as 16.16 fixed point numbers after the matrix
+        let offset = match tkhd.first() {
+            Some(1) => 88,
+            _ => 76,
+        };
+
+        if let (Some(width), Some(height)) = (read_u32(tkhd, offset), read_u32(tkhd, offset + 4)) {
+            // Audio tracks have no dimensions
+            if width >> 16 != 0 && height >> 16 != 0 {
+                data.width = Some(width >> 16);
+                data.height = Some(height >> 16);
+            }
+        }
+    }
+}
+
+/// User data, which contains the location as `©xyz` in videos recorded by most phones
+fn parse_udta(content: &[u8], data: &mut VideoData) {
+    for (kind, xyz) in atoms(content) {
+        if &kind != b"\xA9xyz" || data.lat.is_some() {
+            continue;
+        }
+
+        // Length and language of the string come first
+        let length = read_u16(xyz, 0).unwrap_or(0) as usize;
+        if let Some(location) = xyz.get(4..4 + length) {
+            parse_location(&String::from_utf8_lossy(location), data);
+        }
+    }
+}
+
+/// QuickTime metadata as written by Apple devices, the location and the local creation time
+/// are stored as `com.apple.quicktime.*` keys
+fn parse_meta(content: &[u8], data: &mut VideoData) {
+    // In contrast to MP4, QuickTime does not use version and flags for this atom
+    let content = match content.get(4..8) {
+        Some(b"hdlr") => content,
+        _ => content.get(4..).unwrap_or_default(),
+    };
+
+    let mut keys = Vec::new();
+
+    for (kind, atom) in atoms(content) {
+        match &kind {
+            b"keys" => {
+                keys = atoms(atom.get(8..).unwrap_or_default())
+                    .into_iter()
+                    .map(|(_, key)| String::from_utf8_lossy(key).into_owned())
+                    .collect();
+            }
+            b"ilst" => {
+                for (index, item) in atoms(atom) {
+                    let Some(key) = keys.get((u32::from_be_bytes(index) as usize).wrapping_sub(1))
+                    else {
+                        continue;
+                    };
+
+                    let Some(value) = atoms(item)
+                        .into_iter()
+                        .find(|(kind, _)| kind == b"data")
+                        .and_then(|(_, value)| value.get(8..))
+                        .map(String::from_utf8_lossy)
+                    else {
+                        continue;
+                    };
+
+                    match key.as_str() {
+                        "com.apple.quicktime.location.ISO6709" => parse_location(&value, data),
+                        "com.apple.quicktime.creationdate" => {
+                            // Unlike the movie header this includes the local offset
+                            if let Some(creation) = parse_creation_date(&value) {
+                                data.creation = Some(creation);
+                            }
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Parses an ISO 6709 location in decimal degrees, e.g. `+52.5163+013.3777+034.000/`
+fn parse_location(location: &str, data: &mut VideoData) {
+    let location = location.trim_end_matches(['/', '\0']);
+    let mut parts = Vec::new();
+    let mut start = 0;
+
+    for (index, c) in location.char_indices().skip(1) {
+        if c == '+' || c == '-' {
+            parts.push(&location[start..index]);
+            start = index;
+        }
+    }
+    parts.push(&location[start..]);
+
+    let numbers: Vec<f64> = parts.iter().filter_map(|part| part.parse().ok()).collect();
+
+    if let [lat, lng, ref rest @ ..] = numbers[..] {
+        if lat.abs() <= 90.0 && lng.abs() <= 180.0 {
+            data.lat = Some(lat);
+            data.lng = Some(lng);
+            data.alt = rest.first().copied();
+        }
+    }
+}
+
+/// Parses the Apple creation date, which is almost RFC3339 except for the missing colon in the
+/// offset, e.g. `2023-07-14T18:30:05+0200`
+fn parse_creation_date(date: &str) -> Option<OffsetDateTime> {
+    let date = date.trim_end_matches('\0');
+
+    if let Ok(parsed) = OffsetDateTime::parse(date, &Rfc3339) {
+        return Some(parsed);
+    }
+
+    let split = date.len().checked_sub(5)?;
+    let (local, offset) = (date.get(..split)?, date.get(split..)?);
+    let sign = match offset.as_bytes().first()? {
+        b'+' => 1,
+        b'-' => -1,
+        _ => return None,
+    };
+    let hours: i8 = offset.get(1..3)?.parse().ok()?;
+    let minutes: i8 = offset.get(3..5)?.parse().ok()?;
+    let offset = UtcOffset::from_hms(sign * hours, sign * minutes, 0).ok()?;
+
+    let parsed = OffsetDateTime::parse(&format!("{local}Z"), &Rfc3339).ok()?;
+    Some(parsed.replace_offset(offset))
+}
+
+impl Extractor for VideoExtractor {
+    fn name(&self) -> &str {
+        "video"
+    }
+
+    fn entry_added(
+        &mut self,
+        handle: &mut Handle,
+        entity: &Entity,
+        attribute: &Attribute,
+        value: &Value,
+    ) -> Result<(), Box<dyn Error>> {
+        if attribute == &MimeInfer::attribute() {
+            match value {
+                Value::Data(mime) if mime.starts_with("video") => {
+                    self.extract_video(handle, entity)?;
+                }
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod does {
+    use super::*;
+    use std::io::Cursor;
+
+    fn atom(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut atom = ((content.len() + 8) as u32).to_be_bytes().to_vec();
+        atom.extend(kind);
+        atom.extend(content);
+        atom
+    }
+
+    fn mvhd(creation: u32, timescale: u32, duration: u32) -> Vec<u8> {
+        let mut content = vec![0; 4];
+        for value in [creation, creation, timescale, duration] {
+            content.extend(value.to_be_bytes());
+        }
+        content.resize(100, 0);
+        atom(b"mvhd", &content)
+    }
+
+    fn trak(width: u32, height: u32) -> Vec<u8> {
+        let mut tkhd = vec![0; 76];
+        tkhd.extend((width << 16).to_be_bytes());
+        tkhd.extend((height << 16).to_be_bytes());
+        atom(b"trak", &atom(b"tkhd", &tkhd))
+    }
+
+    fn movie(moov: &[Vec<u8>]) -> Cursor<Vec<u8>> {
+        let mut file = atom(b"ftyp", b"isom\0\0\0\0");
+        file.extend(atom(b"mdat", &[0; 64]));
+        file.extend(atom(b"moov", &moov.concat()));
+        Cursor::new(file)
+    }
+
+    #[test]
+    fn read_mp4_metadata() -> Result<(), Box<dyn Error>> {
+        let location = b"+52.5163+013.3777+034.000/";
+        let mut xyz = (location.len() as u16).to_be_bytes().to_vec();
+        xyz.extend([0x15, 0xC7]);
+        xyz.extend(location);
+
+        // 2023-07-14T16:30:05Z in seconds since 1904
+        let mut file = movie(&[
+            mvhd(3_772_197_005, 600, 6300),
+            trak(0, 0),
+            trak(1920, 1080),
+            atom(b"udta", &atom(b"\xA9xyz", &xyz)),
+        ]);
+        let data = VideoExtractor::extract(&mut file)?;
+
+        assert_eq!(data.duration, Some(10.5));
+        assert_eq!((data.width, data.height), (Some(1920), Some(1080)));
+        assert_eq!(
+            data.creation.map(|c| c.format(&Rfc3339).unwrap()),
+            Some("2023-07-14T16:30:05Z".into())
+        );
+        assert_eq!((data.lat, data.lng), (Some(52.5163), Some(13.3777)));
+        assert_eq!(data.alt, Some(34.0));
+
+        Ok(())
+    }
+
+    #[test]
+    fn prefer_quicktime_metadata() -> Result<(), Box<dyn Error>> {
+        let keys = [
+            "com.apple.quicktime.location.ISO6709",
+            "com.apple.quicktime.creationdate",
+        ];
+        let values = ["-33.8568+151.2153/", "2023-07-14T18:30:05+1000"];
+
+        let mut keys_atom = vec![0; 4];
+        keys_atom.extend((keys.len() as u32).to_be_bytes());
+        let mut ilst = Vec::new();
+
+        for (index, (key, value)) in keys.iter().zip(values).enumerate() {
+            keys_atom.extend(atom(b"mdta", key.as_bytes()));
+
+            let mut data = vec![0, 0, 0, 1, 0, 0, 0, 0];
+            data.extend(value.as_bytes());
+            ilst.extend(atom(
+                &(index as u32 + 1).to_be_bytes(),
+                &atom(b"data", &data),
+            ));
+        }
+
+        let meta = [
+            atom(b"hdlr", &[0; 24]),
+            atom(b"keys", &keys_atom),
+            atom(b"ilst", &ilst),
+        ];
+        let mut file = movie(&[mvhd(3_772_197_005, 600, 600), atom(b"meta", &meta.concat())]);
+        let data = VideoExtractor::extract(&mut file)?;
+
+        assert_eq!((data.lat, data.lng), (Some(-33.8568), Some(151.2153)));
+        assert_eq!(
+            data.creation.map(|c| c.format(&Rfc3339).unwrap()),
+            Some("2023-07-14T18:30:05+10:00".into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reject_malformed_input() {
+        assert!(parse_creation_date("2023-07-14T18:30:05+0200").is_some());
+        assert_eq!(parse_creation_date("2023-07-14T18:30:0€"), None);
+        assert_eq!(parse_creation_date("€€"), None);
+
+        // Version 1 header with a creation time beyond what fits into an i64
+        let mut content = vec![1, 0, 0, 0];
+        content.extend(u64::MAX.to_be_bytes());
+        content.extend(0u64.to_be_bytes());
+        content.extend(600u32.to_be_bytes());
+        content.extend(6000u64.to_be_bytes());
+        let mut data = VideoData::default();
+        parse_mvhd(&content, &mut data);
+        assert_eq!((data.creation, data.duration), (None, Some(10.0)));
+
+        // Large size reaching past the end of any file
+        let mut file = atom(b"ftyp", b"isom\0\0\0\0");
+        file.extend(1u32.to_be_bytes());
+        file.extend(b"mdat");
+        file.extend(u64::MAX.to_be_bytes());
+        assert!(VideoExtractor::extract(&mut Cursor::new(file)).is_err());
+        assert!(atoms(&[0, 0, 0, 1, b'm', b'd', b'a', b't', 0xFF, 0xFF, 0xFF, 0xFF]).is_empty());
+    }
+}
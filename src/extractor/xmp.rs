@@ -0,0 +1,953 @@
+use super::{Extractor, Failure, MimeInfer};
+use crate::{
+    db::{Attribute, Entity, Value},
+    handle::Handle,
+};
+use quick_xml::{
+    escape::resolve_predefined_entity,
+    events::{BytesStart, Event},
+    name::ResolveResult,
+    NsReader, XmlVersion,
+};
+use std::{
+    error::Error,
+    fs,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+const NS_RDF: &str = "http://www.w3.org/1999/02/22-rdf-syntax-ns#";
+const NS_XML: &str = "http://www.w3.org/XML/1998/namespace";
+const NS_DC: &str = "http://purl.org/dc/elements/1.1/";
+const NS_XMP: &str = "http://ns.adobe.com/xap/1.0/";
+const NS_MWG_RS: &str = "http://www.metadataworkinggroup.com/schemas/regions/";
+const NS_ST_AREA: &str = "http://ns.adobe.com/xmp/sType/Area#";
+
+/// Extensions of images that sidecars are looked up for, in lower or upper case
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "gif", "webp", "tif", "tiff", "heic", "heif", "avif", "cr2", "cr3",
+    "nef", "arw", "dng", "raf", "orf", "rw2", "pef",
+];
+
+/// Sidecars that could not be read, along with the reason
+pub type BrokenSidecars = Vec<(PathBuf, Box<dyn Error>)>;
+
+/// Reads ratings, keywords, captions and face regions from embedded XMP and IPTC metadata as
+/// well as from `.xmp` sidecar files next to the image, as written by Lightroom or digiKam
+pub struct XmpExtractor;
+
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct XmpData {
+    pub keywords: Vec<String>,
+    /// Star rating between 0 and 5, -1 marks rejected images
+    pub rating: Option<i32>,
+    /// Color label
+    pub label: Option<String>,
+    pub title: Option<String>,
+    pub caption: Option<String>,
+    pub creators: Vec<String>,
+    pub regions: Vec<Region>,
+}
+
+/// Named area of an image, coordinates are relative to the image size with `x` and `y`
+/// pointing at the center of the area
+#[derive(Debug, Clone, PartialEq)]
+pub struct Region {
+    /// Kind of region as defined by the MWG, e.g. `Face` or `Pet`
+    pub kind: Option<String>,
+    pub name: Option<String>,
+    pub x: f64,
+    pub y: f64,
+    pub width: f64,
+    pub height: f64,
+}
+
+impl XmpData {
+    /// Parses an XMP packet, either embedded in an image or from a sidecar file
+    pub fn parse(xml: &str) -> Result<Self, Box<dyn Error>> {
+        let root = Element::parse(xml)?;
+        let mut data = Self::default();
+
+        for description in root.descriptions() {
+            let mut partial = Self::default();
+
+            if let Some(subject) = description.property(NS_DC, "subject") {
+                partial.keywords = subject.items().iter().filter_map(|i| i.text()).collect();
+            }
+
+            if let Some(creator) = description.property(NS_DC, "creator") {
+                partial.creators = creator.items().iter().filter_map(|i| i.text()).collect();
+            }
+
+            partial.rating = description
+                .property(NS_XMP, "Rating")
+                .and_then(|rating| rating.text())
+                .and_then(|rating| rating.parse::<f64>().ok())
+                .map(|rating| rating.round() as i32);
+            partial.label = description
+                .property(NS_XMP, "Label")
+                .and_then(|label| label.text());
+            partial.title = description
+                .property(NS_DC, "title")
+                .and_then(|title| title.text());
+            partial.caption = description
+                .property(NS_DC, "description")
+                .and_then(|caption| caption.text());
+
+            if let Some(list) = description
+                .property(NS_MWG_RS, "Regions")
+                .and_then(|regions| regions.property(NS_MWG_RS, "RegionList"))
+            {
+                partial.regions = list.items().iter().filter_map(Region::parse).collect();
+            }
+
+            data.merge(partial);
+        }
+
+        Ok(data)
+    }
+
+    /// Parses the IPTC IIM records of a JPEG file
+    pub fn parse_iptc(jpeg: &[u8]) -> Self {
+        let mut data = Self::default();
+
+        for (marker, segment) in jpeg_segments(jpeg) {
+            let Some(resources) = segment.strip_prefix(b"Photoshop 3.0\0") else {
+                continue;
+            };
+
+            if marker != 0xED {
+                continue;
+            }
+
+            for (id, resource) in photoshop_resources(resources) {
+                if id != 0x0404 {
+                    continue;
+                }
+
+                for (record, dataset, value) in iim_datasets(resource) {
+                    let value = String::from_utf8_lossy(value).trim().to_owned();
+
+                    match (record, dataset) {
+                        _ if value.is_empty() => {}
+                        (2, 5) => data.title = Some(value),
+                        (2, 25) => data.keywords.push(value),
+                        (2, 80) => data.creators.push(value),
+                        (2, 120) => data.caption = Some(value),
+                        _ => {}
+                    }
+                }
+            }
+        }
+
+        data
+    }
+
+    /// Fills in everything missing with the data from the other source, list values are
+    /// combined instead
+    pub fn merge(&mut self, other: Self) {
+        for keyword in other.keywords {
+            if !self.keywords.contains(&keyword) {
+                self.keywords.push(keyword);
+            }
+        }
+
+        for creator in other.creators {
+            if !self.creators.contains(&creator) {
+                self.creators.push(creator);
+            }
+        }
+
+        self.rating = self.rating.or(other.rating);
+        self.label = self.label.take().or(other.label);
+        self.title = self.title.take().or(other.title);
+        self.caption = self.caption.take().or(other.caption);
+
+        // Regions from different sources would most likely describe the same areas
+        if self.regions.is_empty() {
+            self.regions = other.regions;
+        }
+    }
+}
+
+impl Region {
+    fn parse(item: &Property) -> Option<Self> {
+        let area = item.property(NS_MWG_RS, "Area")?;
+        let coordinate = |name: &str| {
+            area.property(NS_ST_AREA, name)
+                .and_then(|value| value.text())
+                .and_then(|value| value.parse::<f64>().ok())
+        };
+
+        Some(Self {
+            kind: item
+                .property(NS_MWG_RS, "Type")
+                .and_then(|kind| kind.text()),
+            name: item
+                .property(NS_MWG_RS, "Name")
+                .and_then(|name| name.text()),
+            x: coordinate("x")?,
+            y: coordinate("y")?,
+            width: coordinate("w").unwrap_or(0.0),
+            height: coordinate("h").unwrap_or(0.0),
+        })
+    }
+}
+
+impl XmpExtractor {
+    /// Reads the metadata of an image from all sources. Sidecars that cannot be read are
+    /// skipped and returned separately, so a broken one does not hide the embedded metadata.
+    pub fn extract(
+        handle: &Handle,
+        entity: &Entity,
+    ) -> Result<(XmpData, BrokenSidecars), Box<dyn Error>> {
+        let path = handle.blob_path(entity)?;
+        let mut data = XmpData::default();
+        let mut broken = Vec::new();
+
+        // Sidecars take precedence as they are what editing tools write to
+        for sidecar in sidecars(&path) {
+            match fs::read_to_string(&sidecar)
+                .map_err(Box::from)
+                .and_then(|xml| XmpData::parse(&xml))
+            {
+                Ok(sidecar) => data.merge(sidecar),
+                Err(e) => broken.push((sidecar, e)),
+            }
+        }
+
+        let mut bytes = Vec::new();
+        handle.blob(entity)?.read_to_end(&mut bytes)?;
+
+        if let Some(packet) = find_packet(&bytes) {
+            data.merge(XmpData::parse(&String::from_utf8_lossy(packet))?);
+        }
+
+        data.merge(XmpData::parse_iptc(&bytes));
+
+        Ok((data, broken))
+    }
+
+    fn extract_xmp(&self, handle: &mut Handle, entity: &Entity) -> Result<(), Box<dyn Error>> {
+        let (data, broken) = Self::extract(handle, entity)?;
+
+        // Failures are recorded for the sidecar, so they are cleared once it is fixed
+        for (path, error) in broken {
+            if let Some(sidecar) = sidecar_entity(handle, entity, &path) {
+                Failure::new(self.name(), Some(&sidecar), None, error.as_ref(), 1).record(handle);
+            }
+        }
+
+        // Images are processed again whenever a sidecar shows up or changes, the values read
+        // before would be outdated by then
+        Self::retract(handle, entity);
+
+        for keyword in data.keywords {
+            insert_new(handle, entity, "text/keyword", keyword);
+        }
+
+        for creator in data.creators {
+            insert_new(handle, entity, "text/creator", creator);
+        }
+
+        let single = [
+            ("rank/stars", data.rating.map(|rating| rating.to_string())),
+            ("rank/label", data.label),
+            ("text/title", data.title),
+            ("text/caption", data.caption),
+        ];
+
+        for (attribute, value) in single {
+            if let Some(value) = value {
+                handle.insert(entity.clone(), attribute, value);
+            }
+        }
+
+        for (index, region) in data.regions.into_iter().enumerate() {
+            let id = Entity::from(format!("region:{}:{index}", entity.0));
+
+            handle.insert(id.clone(), "region/x", region.x);
+            handle.insert(id.clone(), "region/y", region.y);
+            handle.insert(id.clone(), "region/width", region.width);
+            handle.insert(id.clone(), "region/height", region.height);
+
+            if let Some(kind) = region.kind {
+                handle.insert(id.clone(), "region/kind", kind.to_lowercase());
+            }
+
+            if let Some(name) = region.name {
+                let person = Entity::from(format!("person:{name}"));
+
+                if handle.get(&person, &"text/label".into()).next().is_none() {
+                    handle.insert(person.clone(), "text/label", name);
+                }
+
+                handle.insert(id.clone(), "region/person", person);
+            }
+
+            handle.insert(entity.clone(), "image/region", id);
+        }
+
+        self.link_sidecars(handle, entity)
+    }
+
+    /// Removes everything previously read from the metadata of an image
+    pub(super) fn retract(handle: &mut Handle, entity: &Entity) {
+        let regions: Vec<_> = handle
+            .get(entity, &"image/region".into())
+            .filter_map(|value| match value {
+                Value::Reference(region) => Some(region.clone()),
+                Value::Data(_) => None,
+            })
+            .collect();
+
+        for region in regions {
+            handle.retract_entity(&region);
+        }
+
+        for attribute in [
+            "text/keyword",
+            "text/creator",
+            "rank/stars",
+            "rank/label",
+            "text/title",
+            "text/caption",
+            "image/region",
+        ] {
+            handle.retract_attribute(entity, &attribute.into());
+        }
+    }
+
+    fn link_sidecars(&self, handle: &mut Handle, entity: &Entity) -> Result<(), Box<dyn Error>> {
+        for path in sidecars(&handle.blob_path(entity)?) {
+            if let Some(sidecar) = sidecar_entity(handle, entity, &path) {
+                insert_new(handle, entity, "blob/sidecar", sidecar);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Processes the images a sidecar belongs to again, whenever it is added or changed
+    fn sidecar_added(
+        &self,
+        handle: &mut Handle,
+        sidecar: &Entity,
+        path: &str,
+    ) -> Result<(), Box<dyn Error>> {
+        let root = handle.known(sidecar, "blob/root");
+        let base = Path::new(path).with_extension("");
+        let base = base.to_string_lossy();
+
+        // Candidates for both naming styles, looked up by path instead of scanning all blobs
+        let candidates = IMAGE_EXTENSIONS
+            .iter()
+            .flat_map(|extension| [extension.to_string(), extension.to_ascii_uppercase()])
+            .map(|extension| format!("{base}.{extension}"))
+            .chain([base.to_string()]);
+
+        let blob_path = Attribute::from("blob/path");
+        let images: Vec<Entity> = candidates
+            .flat_map(|candidate| handle.ave.values(&blob_path, &Value::from(candidate)))
+            .filter(|image| *image != sidecar && handle.known(image, "blob/root") == root)
+            .cloned()
+            .collect();
+
+        for image in images {
+            let is_image = handle
+                .known(&image, &MimeInfer::attribute().0)
+                .is_some_and(|mime| mime.starts_with("image"));
+
+            if is_image {
+                self.extract_xmp(handle, &image)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Extractor for XmpExtractor {
+    fn name(&self) -> &str {
+        "xmp"
+    }
+
+    fn entry_added(
+        &mut self,
+        handle: &mut Handle,
+        entity: &Entity,
+        attribute: &Attribute,
+        value: &Value,
+    ) -> Result<(), Box<dyn Error>> {
+        match value {
+            Value::Data(mime)
+                if attribute == &MimeInfer::attribute() && mime.starts_with("image") =>
+            {
+                self.extract_xmp(handle, entity)?;
+            }
+            Value::Data(path)
+                if attribute == &Attribute::from("blob/path") && is_sidecar(Path::new(path)) =>
+            {
+                self.sidecar_added(handle, entity, path)?;
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+}
+
+fn is_sidecar(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("xmp"))
+}
+
+/// Existing sidecars of an image. They are either named like the image with the extension
+/// replaced (`IMG_1.xmp`) or appended (`IMG_1.jpg.xmp`), depending on the tool that wrote them.
+fn sidecars(path: &Path) -> Vec<PathBuf> {
+    let replaced = |extension: &str| path.with_extension(extension);
+    let appended = |extension: &str| {
+        let mut appended = path.as_os_str().to_owned();
+        appended.push(".");
+        appended.push(extension);
+        PathBuf::from(appended)
+    };
+
+    // Only take the first match per style, both spellings point to the same file on case
+    // insensitive file systems
+    [
+        [replaced("xmp"), replaced("XMP")],
+        [appended("xmp"), appended("XMP")],
+    ]
+    .into_iter()
+    .filter_map(|candidates| candidates.into_iter().find(|c| c.is_file()))
+    .collect()
+}
+
+/// Entity of a sidecar file, which is stored in the same root as its image
+fn sidecar_entity(handle: &Handle, image: &Entity, path: &Path) -> Option<Entity> {
+    let root = handle
+        .known(image, "blob/root")
+        .unwrap_or_else(|| handle.primary_root().name.clone());
+    let relative = path.strip_prefix(&handle.root(&root)?.path).ok()?;
+
+    Some(handle.blob_entity(&root, relative.to_str()?))
+}
+
+fn insert_new(handle: &mut Handle, entity: &Entity, attribute: &str, value: impl Into<Value>) {
+    let value = value.into();
+
+    if !handle.get(entity, &attribute.into()).any(|v| v == &value) {
+        handle.insert(entity.clone(), attribute, value);
+    }
+}
+
+/// Finds an XMP packet anywhere in a file, which works for all container formats
+fn find_packet(bytes: &[u8]) -> Option<&[u8]> {
+    const START: &[u8] = b"<x:xmpmeta";
+    const END: &[u8] = b"</x:xmpmeta>";
+
+    let start = bytes.windows(START.len()).position(|w| w == START)?;
+    let length = bytes[start..].windows(END.len()).position(|w| w == END)?;
+
+    Some(&bytes[start..start + length + END.len()])
+}
+
+/// Splits a JPEG file into its marker segments, up to the start of the image data
+fn jpeg_segments(jpeg: &[u8]) -> Vec<(u8, &[u8])> {
+    let mut segments = Vec::new();
+
+    if !jpeg.starts_with(&[0xFF, 0xD8]) {
+        return segments;
+    }
+
+    let mut position = 2;
+
+    while let [0xFF, marker, high, low, ..] = jpeg[position..] {
+        // Start of scan, everything after it is compressed image data
+        if marker == 0xDA {
+            break;
+        }
+
+        let length = u16::from_be_bytes([high, low]) as usize;
+        let Some(segment) = jpeg.get(position + 4..position + 2 + length) else {
+            break;
+        };
+
+        segments.push((marker, segment));
+        position += 2 + length;
+    }
+
+    segments
+}
+
+/// Splits Photoshop image resource blocks (`8BIM`) into their IDs and data
+fn photoshop_resources(mut data: &[u8]) -> Vec<(u16, &[u8])> {
+    let mut resources = Vec::new();
+
+    while let Some(rest) = data.strip_prefix(b"8BIM") {
+        let [id_high, id_low, name_length, ..] = *rest else {
+            break;
+        };
+
+        // The name is a pascal string padded to an even length, including the length byte
+        let name = (name_length as usize + 2) & !1;
+        let Some([a, b, c, d]) = rest.get(2 + name..6 + name) else {
+            break;
+        };
+
+        let size = u32::from_be_bytes([*a, *b, *c, *d]) as usize;
+        let start = 6 + name;
+        let Some(resource) = rest.get(start..start + size) else {
+            break;
+        };
+
+        resources.push((u16::from_be_bytes([id_high, id_low]), resource));
+        data = rest.get(start + ((size + 1) & !1)..).unwrap_or_default();
+    }
+
+    resources
+}
+
+/// Splits IPTC IIM data into record number, dataset number and value
+fn iim_datasets(mut data: &[u8]) -> Vec<(u8, u8, &[u8])> {
+    let mut datasets = Vec::new();
+
+    while let [0x1C, record, dataset, high, low, ..] = *data {
+        // Extended datasets (with the high bit set) are not used for any text fields
+        if high & 0x80 != 0 {
+            break;
+        }
+
+        let size = u16::from_be_bytes([high, low]) as usize;
+        let Some(value) = data.get(5..5 + size) else {
+            break;
+        };
+
+        datasets.push((record, dataset, value));
+        data = &data[5 + size..];
+    }
+
+    datasets
+}
+
+/// Minimal XML tree with resolved namespaces, just enough to navigate RDF
+#[derive(Debug, Default)]
+struct Element {
+    namespace: String,
+    name: String,
+    attributes: Vec<(String, String, String)>,
+    children: Vec<Element>,
+    text: String,
+}
+
+/// Value of an XMP property, which is either stored in an attribute or an element
+#[derive(Clone, Copy)]
+enum Property<'a> {
+    Attribute(&'a str),
+    Element(&'a Element),
+}
+
+impl Element {
+    fn parse(xml: &str) -> Result<Self, quick_xml::Error> {
+        let mut reader = NsReader::from_str(xml);
+        let mut stack = vec![Self::default()];
+
+        loop {
+            let (namespace, event) = reader.read_resolved_event()?;
+            let namespace = match namespace {
+                ResolveResult::Bound(namespace) => namespace.0.to_owned(),
+                _ => String::new(),
+            };
+
+            match event {
+                Event::Start(start) => {
+                    stack.push(Self::from_start(&reader, namespace, &start));
+                }
+                Event::Empty(start) => {
+                    let element = Self::from_start(&reader, namespace, &start);
+                    if let Some(parent) = stack.last_mut() {
+                        parent.children.push(element);
+                    }
+                }
+                Event::End(_) if stack.len() > 1 => {
+                    let element = stack.pop().unwrap_or_default();
+                    if let Some(parent) = stack.last_mut() {
+                        parent.children.push(element);
+                    }
+                }
+                Event::Text(text) => {
+                    if let Some(element) = stack.last_mut() {
+                        element.text.push_str(&text.xml10_content());
+                    }
+                }
+                Event::CData(data) => {
+                    if let Some(element) = stack.last_mut() {
+                        element.text.push_str(&data.xml10_content());
+                    }
+                }
+                Event::GeneralRef(reference) => {
+                    let resolved = match reference.resolve_char_ref()? {
+                        Some(c) => Some(c.to_string()),
+                        None => resolve_predefined_entity(&reference).map(str::to_owned),
+                    };
+
+                    if let (Some(element), Some(resolved)) = (stack.last_mut(), resolved) {
+                        element.text.push_str(&resolved);
+                    }
+                }
+                Event::Eof => break,
+                _ => {}
+            }
+        }
+
+        Ok(stack.swap_remove(0))
+    }
+
+    fn from_start(reader: &NsReader<&[u8]>, namespace: String, start: &BytesStart) -> Self {
+        let attributes = start
+            .attributes()
+            .flatten()
+            .filter(|attribute| attribute.key.as_namespace_binding().is_none())
+            .filter_map(|attribute| {
+                let (namespace, name) = reader.resolver().resolve_attribute(attribute.key);
+                let namespace = match namespace {
+                    ResolveResult::Bound(namespace) => namespace.0.to_owned(),
+                    _ => String::new(),
+                };
+                let value = attribute.normalized_value(XmlVersion::Implicit1_0).ok()?;
+
+                Some((namespace, name.into_inner().to_owned(), value.into_owned()))
+            })
+            .collect();
+
+        Self {
+            namespace,
+            name: start.local_name().into_inner().to_owned(),
+            attributes,
+            ..Default::default()
+        }
+    }
+
+    fn is(&self, namespace: &str, name: &str) -> bool {
+        self.namespace == namespace && self.name == name
+    }
+
+    fn attribute(&self, namespace: &str, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(ns, n, _)| ns == namespace && n == name)
+            .map(|(_, _, value)| value.as_str())
+    }
+
+    /// All top level `rdf:Description` elements
+    fn descriptions(&self) -> Vec<&Element> {
+        if self.is(NS_RDF, "RDF") {
+            return self
+                .children
+                .iter()
+                .filter(|child| child.is(NS_RDF, "Description"))
+                .collect();
+        }
+
+        self.children
+            .iter()
+            .flat_map(|child| child.descriptions())
+            .collect()
+    }
+
+    /// Looks up a property of a description or struct, which may be stored in an attribute,
+    /// a child element or a nested `rdf:Description`
+    fn property(&self, namespace: &str, name: &str) -> Option<Property<'_>> {
+        if let Some(value) = self.attribute(namespace, name) {
+            return Some(Property::Attribute(value));
+        }
+
+        for child in self.children.iter() {
+            if child.is(namespace, name) {
+                return Some(Property::Element(child));
+            }
+        }
+
+        self.children
+            .iter()
+            .filter(|child| child.is(NS_RDF, "Description"))
+            .find_map(|child| child.property(namespace, name))
+    }
+}
+
+impl<'a> Property<'a> {
+    /// Text of a simple property, or the default entry of a language alternative
+    fn text(self) -> Option<String> {
+        let text = match self {
+            Property::Attribute(value) => value.to_string(),
+            Property::Element(element) => {
+                let items = self.items();
+                let default = items.iter().find(|item| match item {
+                    Property::Element(item) => item.attribute(NS_XML, "lang") == Some("x-default"),
+                    Property::Attribute(_) => false,
+                });
+
+                match default.or(items.first()).copied() {
+                    Some(item) if element.children.iter().any(is_container) => item.text()?,
+                    _ => element.text.clone(),
+                }
+            }
+        };
+
+        let text = text.trim();
+        (!text.is_empty()).then(|| text.to_owned())
+    }
+
+    /// Entries of a bag, sequence or alternative, a single value is treated as one entry
+    fn items(self) -> Vec<Property<'a>> {
+        match self {
+            Property::Attribute(value) => vec![Property::Attribute(value)],
+            Property::Element(element) => {
+                match element.children.iter().find(|child| is_container(child)) {
+                    Some(container) => container
+                        .children
+                        .iter()
+                        .filter(|item| item.is(NS_RDF, "li"))
+                        .map(Property::Element)
+                        .collect(),
+                    None => vec![Property::Element(element)],
+                }
+            }
+        }
+    }
+
+    fn property(self, namespace: &str, name: &str) -> Option<Property<'a>> {
+        match self {
+            Property::Attribute(_) => None,
+            Property::Element(element) => element.property(namespace, name),
+        }
+    }
+}
+
+fn is_container(element: &Element) -> bool {
+    ["Bag", "Seq", "Alt"]
+        .iter()
+        .any(|name| element.is(NS_RDF, name))
+}
+
+#[cfg(test)]
+mod does {
+    use super::*;
+    use crate::db::Database;
+
+    const SIDECAR: &str = r#"<x:xmpmeta xmlns:x="adobe:ns:meta/">
+ <rdf:RDF xmlns:rdf="http://www.w3.org/1999/02/22-rdf-syntax-ns#">
+  <rdf:Description rdf:about=""
+    xmlns:xmp="http://ns.adobe.com/xap/1.0/"
+    xmlns:dc="http://purl.org/dc/elements/1.1/"
+    xmlns:mwg-rs="http://www.metadataworkinggroup.com/schemas/regions/"
+    xmlns:stArea="http://ns.adobe.com/xmp/sType/Area#"
+    xmp:Rating="4"
+    xmp:Label="Red">
+   <dc:subject>
+    <rdf:Bag>
+     <rdf:li>beach</rdf:li>
+     <rdf:li>sunset &amp; sea</rdf:li>
+    </rdf:Bag>
+   </dc:subject>
+   <dc:description>
+    <rdf:Alt>
+     <rdf:li xml:lang="de">Am Strand</rdf:li>
+     <rdf:li xml:lang="x-default">At the beach</rdf:li>
+    </rdf:Alt>
+   </dc:description>
+   <mwg-rs:Regions rdf:parseType="Resource">
+    <mwg-rs:RegionList>
+     <rdf:Bag>
+      <rdf:li>
+       <rdf:Description mwg-rs:Name="Alice" mwg-rs:Type="Face">
+        <mwg-rs:Area stArea:x="0.25" stArea:y="0.5" stArea:w="0.1" stArea:h="0.2" stArea:unit="normalized"/>
+       </rdf:Description>
+      </rdf:li>
+     </rdf:Bag>
+    </mwg-rs:RegionList>
+   </mwg-rs:Regions>
+  </rdf:Description>
+ </rdf:RDF>
+</x:xmpmeta>"#;
+
+    #[test]
+    fn parse_xmp_packets() -> Result<(), Box<dyn Error>> {
+        let data = XmpData::parse(SIDECAR)?;
+
+        assert_eq!(data.keywords, ["beach", "sunset & sea"]);
+        assert_eq!(data.rating, Some(4));
+        assert_eq!(data.label.as_deref(), Some("Red"));
+        assert_eq!(data.caption.as_deref(), Some("At the beach"));
+        assert_eq!(
+            data.regions,
+            [Region {
+                kind: Some("Face".into()),
+                name: Some("Alice".into()),
+                x: 0.25,
+                y: 0.5,
+                width: 0.1,
+                height: 0.2,
+            }]
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn parse_iptc_records() {
+        let mut iim = Vec::new();
+        for (dataset, value) in [(25, "harbor"), (25, "boats"), (120, "Fishing boats")] {
+            iim.extend([0x1C, 2, dataset, 0, value.len() as u8]);
+            iim.extend(value.as_bytes());
+        }
+
+        let mut resource = b"8BIM\x04\x04\x00\x00".to_vec();
+        resource.extend((iim.len() as u32).to_be_bytes());
+        resource.extend(&iim);
+
+        let mut segment = b"Photoshop 3.0\0".to_vec();
+        segment.extend(resource);
+
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xED];
+        jpeg.extend(((segment.len() + 2) as u16).to_be_bytes());
+        jpeg.extend(segment);
+        jpeg.extend([0xFF, 0xD9]);
+
+        let data = XmpData::parse_iptc(&jpeg);
+        assert_eq!(data.keywords, ["harbor", "boats"]);
+        assert_eq!(data.caption.as_deref(), Some("Fishing boats"));
+    }
+
+    #[test]
+    fn pick_up_sidecars() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let (db, write_log) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+        let mut extractors = crate::make_extractors![XmpExtractor];
+
+        fs::write(dir.path().join("IMG_1.jpg"), b"\xFF\xD8\xFF\xD9")?;
+        fs::write(dir.path().join("IMG_1.xmp"), SIDECAR)?;
+
+        let image = Entity::from("IMG_1.jpg");
+        let sidecar = Entity::from("IMG_1.xmp");
+        handle.insert(image.clone(), "blob/path", "IMG_1.jpg");
+        handle.insert(image.clone(), "type/mime", "image/jpeg");
+        handle.insert(sidecar.clone(), "blob/path", "IMG_1.xmp");
+
+        crate::process_write_log(
+            &write_log,
+            &mut handle,
+            &mut extractors,
+            &Default::default(),
+        )?;
+
+        let fact = |attribute: &str| handle.get(&image, &attribute.into()).count();
+        assert_eq!(fact("text/keyword"), 2);
+        assert_eq!(fact("rank/stars"), 1);
+        assert_eq!(fact("image/region"), 1);
+        assert_eq!(
+            handle.get(&image, &"blob/sidecar".into()).next(),
+            Some(&Value::Reference(sidecar))
+        );
+
+        let person = Entity::from("person:Alice");
+        assert_eq!(
+            handle.get(&person, &"text/label".into()).next(),
+            Some(&"Alice".into())
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn reread_edited_and_broken_sidecars() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let (db, write_log) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+        let mut extractors = crate::make_extractors![XmpExtractor];
+
+        let mut jpeg = b"\xFF\xD8".to_vec();
+        jpeg.extend(SIDECAR.as_bytes());
+        jpeg.extend(b"\xFF\xD9");
+        fs::write(dir.path().join("IMG_1.jpg"), jpeg)?;
+
+        let image = Entity::from("IMG_1.jpg");
+        let sidecar = Entity::from("IMG_1.xmp");
+        handle.insert(image.clone(), "blob/path", "IMG_1.jpg");
+        handle.insert(image.clone(), "type/mime", "image/jpeg");
+
+        // Saving the sidecar again emits its facts again, just like the loader does
+        let mut save = |handle: &mut Handle, xml: &str| -> Result<Vec<Value>, Box<dyn Error>> {
+            fs::write(dir.path().join("IMG_1.xmp"), xml)?;
+            handle.insert(sidecar.clone(), "blob/path", "IMG_1.xmp");
+            crate::process_write_log(&write_log, handle, &mut extractors, &Default::default())?;
+
+            Ok(handle.get(&image, &"rank/stars".into()).cloned().collect())
+        };
+
+        let stars = save(
+            &mut handle,
+            &SIDECAR.replace(r#"Rating="4""#, r#"Rating="1""#),
+        )?;
+        assert_eq!(stars, [Value::from("1")]);
+
+        let stars = save(
+            &mut handle,
+            &SIDECAR.replace(r#"Rating="4""#, r#"Rating="3""#),
+        )?;
+        assert_eq!(stars, [Value::from("3")]);
+        assert_eq!(handle.get(&image, &"text/keyword".into()).count(), 2);
+        assert_eq!(handle.get(&image, &"image/region".into()).count(), 1);
+
+        // The embedded metadata is still used if the sidecar cannot be parsed
+        let stars = save(&mut handle, "<x:xmpmeta><rdf:RDF></x:xmpmeta>")?;
+        assert_eq!(stars, [Value::from("4")]);
+
+        let failures = crate::extractor::FailureReport::load(&handle).failures;
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].entity.as_ref(), Some(&sidecar));
+
+        Ok(())
+    }
+
+    #[test]
+    fn find_images_of_sidecars_by_path() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let (db, write_log) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+        let mut extractors = crate::make_extractors![XmpExtractor];
+
+        for (image, sidecar) in [("IMG_1.JPG", "IMG_1.xmp"), ("IMG_2.png", "IMG_2.png.xmp")] {
+            fs::write(dir.path().join(image), b"\xFF\xD8\xFF\xD9")?;
+            handle.insert(image, "blob/path", image);
+            handle.insert(image, "type/mime", "image/jpeg");
+            crate::process_write_log(
+                &write_log,
+                &mut handle,
+                &mut extractors,
+                &Default::default(),
+            )?;
+
+            // The sidecar shows up after the image has been processed
+            fs::write(dir.path().join(sidecar), SIDECAR)?;
+            handle.insert(sidecar, "blob/path", sidecar);
+            crate::process_write_log(
+                &write_log,
+                &mut handle,
+                &mut extractors,
+                &Default::default(),
+            )?;
+
+            let image = Entity::from(image);
+            assert_eq!(handle.get(&image, &"rank/stars".into()).count(), 1);
+            assert_eq!(
+                handle.get(&image, &"blob/sidecar".into()).next(),
+                Some(&Value::Reference(Entity::from(sidecar)))
+            );
+        }
+
+        Ok(())
+    }
+}
@@ -1,8 +1,7 @@
 use std::{
     collections::{HashMap, HashSet},
     error::Error,
-    fs::File,
-    io::BufWriter,
+    io::Write,
 };
 
 use crate::{db::*, handle::Handle, query};
@@ -26,7 +25,8 @@ struct GraphData {
     links: Vec<Link>,
 }
 
-pub fn export_graph(handle: &Handle) -> Result<(), Box<dyn Error>> {
+/// Writes all entities and the references between them as JSON for graph visualizations
+pub fn export_graph(handle: &Handle, writer: impl Write) -> Result<(), Box<dyn Error>> {
     query!(handle where (#a, #b, :attr, ?label) match [
         { #a, :attr, #b },
         { #a, :"text/label", ?label }
@@ -84,8 +84,6 @@ pub fn export_graph(handle: &Handle) -> Result<(), Box<dyn Error>> {
         links: links.into_iter().collect(),
     };
 
-    let file = File::create("graph.json")?;
-    let writer = BufWriter::new(file);
     serde_json::to_writer(writer, &graph)?;
 
     Ok(())
@@ -1,28 +1,234 @@
-use super::db::{Database, Entity};
+use super::db::{Database, Entity, Value};
+use sha2::{Digest, Sha256};
 use std::{
-    fs::File,
-    io::{self, BufRead, Read, Seek},
+    error::Error,
+    fs::{self, File, Metadata},
+    io::{self, BufRead, BufReader, BufWriter, Read, Seek, Write},
     ops::{Deref, DerefMut},
-    path::PathBuf,
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
 };
+use time::{format_description::well_known::Rfc3339, OffsetDateTime};
+
+static INGEST_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+/// Whether importing a file leaves the original in place
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImportMode {
+    Copy,
+    Move,
+}
+
+/// Name of the storage root passed to [`Handle::new`]
+pub const DEFAULT_ROOT: &str = "default";
+
+/// Directory within the primary root that derived blobs (e.g. thumbnails) are stored in,
+/// hidden so the loader does not treat them like regular blobs
+pub const DERIVED_DIR: &str = ".derived";
+
+/// Named directory in which blobs are stored
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StorageRoot {
+    pub name: String,
+    pub path: PathBuf,
+}
+
+impl StorageRoot {
+    pub fn new(name: impl Into<String>, path: impl Into<PathBuf>) -> Self {
+        Self {
+            name: name.into(),
+            path: path.into(),
+        }
+    }
+}
 
 pub struct Handle {
     db: Database,
-    storage: PathBuf,
+    /// All storage roots, the first one is where ingested blobs end up
+    roots: Vec<StorageRoot>,
 }
 
 impl Handle {
     pub fn new(db: Database, storage: PathBuf) -> Self {
-        Self { db, storage }
+        Self {
+            db,
+            roots: vec![StorageRoot::new(DEFAULT_ROOT, storage)],
+        }
+    }
+
+    /// Adds another storage root, replacing any previous root with the same name
+    pub fn add_root(&mut self, root: StorageRoot) {
+        match self.roots.iter_mut().find(|r| r.name == root.name) {
+            Some(existing) => *existing = root,
+            None => self.roots.push(root),
+        }
+    }
+
+    pub fn roots(&self) -> &[StorageRoot] {
+        &self.roots
+    }
+
+    pub fn root(&self, name: &str) -> Option<&StorageRoot> {
+        self.roots.iter().find(|root| root.name == name)
+    }
+
+    /// Root that newly ingested blobs are stored in
+    pub fn primary_root(&self) -> &StorageRoot {
+        &self.roots[0]
+    }
+
+    /// Derives the entity for a blob stored at the given path, relative to a storage root.
+    /// Blobs in the primary root are identified by their path alone, all others are
+    /// prefixed with the name of the root to keep them apart. Root names cannot contain `:`,
+    /// so primary paths which do are prefixed as well, e.g. `default:photos:a.jpg` rather
+    /// than colliding with `a.jpg` in the `photos` root.
+    pub fn blob_entity(&self, root: &str, path: &str) -> Entity {
+        if root == self.primary_root().name && !path.contains(':') {
+            Entity::from(path)
+        } else {
+            Entity::from(format!("{root}:{path}"))
+        }
+    }
+
+    /// First data value of an attribute, e.g. one of the `blob/*` facts of a blob
+    pub fn known(&self, entity: &Entity, attribute: &str) -> Option<String> {
+        self.get(entity, &attribute.into())
+            .find_map(|value| match value {
+                Value::Data(data) => Some(data.clone()),
+                Value::Reference(_) => None,
+            })
     }
 
-    pub fn blob(&self, entity: &Entity) -> Result<impl BufRead + Read + Seek, io::Error> {
-        if entity.0.contains("/") {
+    /// Resolves the location of a blob using its `blob/root` and `blob/path` facts, falling
+    /// back to treating the entity as a path within the primary root
+    pub fn blob_path(&self, entity: &Entity) -> Result<PathBuf, io::Error> {
+        let fact = |attribute: &str| {
+            self.get(entity, &attribute.into())
+                .find_map(|value| match value {
+                    Value::Data(data) => Some(data.as_str()),
+                    Value::Reference(_) => None,
+                })
+        };
+
+        let root = match fact("blob/root") {
+            Some(name) => self
+                .root(name)
+                .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "unknown storage root"))?,
+            None => self.primary_root(),
+        };
+
+        let path = Path::new(fact("blob/path").unwrap_or(&entity.0));
+
+        // Prevent entities from escaping the storage directory
+        if !path.components().all(|c| matches!(c, Component::Normal(_))) {
             return Err(io::Error::new(io::ErrorKind::NotFound, "invalid entity ID"));
         }
 
-        let file = File::open(self.storage.join(&entity.0))?;
-        Ok(std::io::BufReader::new(file))
+        Ok(root.path.join(path))
+    }
+
+    /// Entity and location of a blob derived from another one, e.g. `thumbnail` or `preview`
+    pub fn derived_blob(&self, source: &Entity, kind: &str, extension: &str) -> (Entity, PathBuf) {
+        let path = format!("{DERIVED_DIR}/{kind}/{}.{extension}", source.0);
+        let root = self.primary_root();
+
+        (self.blob_entity(&root.name, &path), root.path.join(path))
+    }
+
+    pub fn blob(&self, entity: &Entity) -> Result<impl BufRead + Seek, io::Error> {
+        let file = File::open(self.blob_path(entity)?)?;
+        Ok(BufReader::new(file))
+    }
+
+    /// Stores a blob under the hash of its contents and returns the resulting entity.
+    ///
+    /// Ingesting identical contents multiple times yields the same entity and only keeps a
+    /// single copy on disk, the original file names are recorded as `blob/name` facts.
+    pub fn ingest(
+        &mut self,
+        mut reader: impl Read,
+        name: Option<&str>,
+    ) -> Result<Entity, Box<dyn Error>> {
+        // Hidden so the loader does not pick up partially written files
+        let temp = self.primary_root().path.join(format!(
+            ".ingest-{}-{}",
+            std::process::id(),
+            INGEST_COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let hash = match write_hashed(&mut reader, &temp) {
+            Ok(hash) => hash,
+            Err(e) => {
+                fs::remove_file(&temp).ok();
+                return Err(e.into());
+            }
+        };
+
+        let path = self.primary_root().path.join(&hash);
+        if path.exists() {
+            fs::remove_file(&temp)?;
+        } else {
+            fs::rename(&temp, &path)?;
+        }
+
+        self.register(hash, name)
+    }
+
+    /// Imports a file into the storage, see [`Handle::ingest`] for details.
+    ///
+    /// The inserted `blob/*` facts end up in the write log like any other fact, so the blob
+    /// is processed by the extractors the next time the write log gets drained.
+    pub fn import(
+        &mut self,
+        path: impl AsRef<Path>,
+        mode: ImportMode,
+    ) -> Result<Entity, Box<dyn Error>> {
+        let path = path.as_ref();
+        let name = path.file_name().and_then(|name| name.to_str());
+
+        if mode == ImportMode::Copy {
+            return self.ingest(BufReader::new(File::open(path)?), name);
+        }
+
+        // Renaming is way cheaper than copying but only works within the same file system
+        let hash = content_hash(BufReader::new(File::open(path)?))?;
+        let target = self.primary_root().path.join(&hash);
+
+        if target.exists() {
+            fs::remove_file(path)?;
+            self.register(hash, name)
+        } else if fs::rename(path, &target).is_ok() {
+            self.register(hash, name)
+        } else {
+            let entity = self.ingest(BufReader::new(File::open(path)?), name)?;
+            fs::remove_file(path)?;
+            Ok(entity)
+        }
+    }
+
+    /// Inserts the facts for a blob stored under its hash, unless they already exist
+    fn register(&mut self, hash: String, name: Option<&str>) -> Result<Entity, Box<dyn Error>> {
+        let root = self.primary_root().clone();
+        let entity = self.blob_entity(&root.name, &hash);
+
+        if self.get(&entity, &"blob/size".into()).next().is_none() {
+            let metadata = fs::metadata(root.path.join(&hash))?;
+            self.insert(entity.clone(), "blob/size", metadata.len());
+            self.insert(entity.clone(), "blob/mtime", modification_time(&metadata)?);
+            self.insert(entity.clone(), "blob/root", root.name);
+            self.insert(entity.clone(), "blob/path", hash.as_str());
+            self.insert(entity.clone(), "blob/hash", hash);
+        }
+
+        if let Some(name) = name {
+            let name = Value::from(name);
+
+            if !self.get(&entity, &"blob/name".into()).any(|n| n == &name) {
+                self.insert(entity.clone(), "blob/name", name);
+            }
+        }
+
+        Ok(entity)
     }
 }
 
@@ -39,3 +245,123 @@ impl DerefMut for Handle {
         &mut self.db
     }
 }
+
+/// Computes the hex encoded SHA-256 digest of everything the reader yields
+pub fn content_hash(mut reader: impl Read) -> Result<String, io::Error> {
+    let mut hasher = Sha256::new();
+    io::copy(&mut reader, &mut hasher)?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Formats the modification time of a file the way it is stored in `blob/mtime` facts
+pub fn modification_time(metadata: &Metadata) -> Result<String, Box<dyn Error>> {
+    Ok(OffsetDateTime::from(metadata.modified()?).format(&Rfc3339)?)
+}
+
+/// Copies everything from the reader into a new file while hashing it
+fn write_hashed(reader: &mut impl Read, path: &Path) -> Result<String, io::Error> {
+    let mut writer = HashingWriter {
+        inner: BufWriter::new(File::create(path)?),
+        hasher: Sha256::new(),
+    };
+
+    io::copy(reader, &mut writer)?;
+    writer.inner.flush()?;
+
+    Ok(format!("{:x}", writer.hasher.finalize()))
+}
+
+struct HashingWriter<W> {
+    inner: W,
+    hasher: Sha256,
+}
+
+impl<W: Write> Write for HashingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod does {
+    use super::*;
+
+    #[test]
+    fn keep_blob_entities_of_roots_apart() {
+        let (db, _) = Database::new();
+        let mut handle = Handle::new(db, "storage".into());
+        handle.add_root(StorageRoot::new("photos", "photos"));
+
+        assert_eq!(handle.blob_entity(DEFAULT_ROOT, "a.jpg").0, "a.jpg");
+        assert_eq!(handle.blob_entity("photos", "a.jpg").0, "photos:a.jpg");
+        assert_eq!(
+            handle.blob_entity(DEFAULT_ROOT, "photos:a.jpg").0,
+            "default:photos:a.jpg"
+        );
+    }
+
+    #[test]
+    fn deduplicate_ingested_blobs() -> Result<(), Box<dyn Error>> {
+        let dir = tempfile::tempdir()?;
+        let (db, _) = Database::new();
+        let mut handle = Handle::new(db, dir.path().into());
+
+        let a = handle.ingest("same contents".as_bytes(), Some("a.jpg"))?;
+        let b = handle.ingest("same contents".as_bytes(), Some("b.jpg"))?;
+        let c = handle.ingest("other contents".as_bytes(), None)?;
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_eq!(a.0, content_hash("same contents".as_bytes())?);
+        assert_eq!(handle.get(&a, &"blob/name".into()).count(), 2);
+        assert_eq!(handle.get(&a, &"blob/size".into()).count(), 1);
+        assert_eq!(fs::read_dir(dir.path())?.count(), 2);
+
+        let mut contents = String::new();
+        handle.blob(&a)?.read_to_string(&mut contents)?;
+        assert_eq!(contents, "same contents");
+
+        Ok(())
+    }
+
+    #[test]
+    fn import_files_into_the_pipeline() -> Result<(), Box<dyn Error>> {
+        let storage = tempfile::tempdir()?;
+        let source = tempfile::tempdir()?;
+        let (db, write_log) = Database::new();
+        let mut handle = Handle::new(db, storage.path().into());
+        let mut extractors = crate::make_extractors![crate::extractor::MimeInfer::new()];
+
+        let copied = source.path().join("copied.png");
+        let moved = source.path().join("moved.txt");
+        fs::write(&copied, b"\x89PNG\r\n\x1a\n")?;
+        fs::write(&moved, "moved")?;
+
+        let copied_entity = handle.import(&copied, ImportMode::Copy)?;
+        let moved_entity = handle.import(&moved, ImportMode::Move)?;
+        assert!(copied.exists());
+        assert!(!moved.exists());
+
+        crate::process_write_log(
+            &write_log,
+            &mut handle,
+            &mut extractors,
+            &Default::default(),
+        )?;
+
+        let mime = handle.get(&copied_entity, &"type/mime".into()).next();
+        assert_eq!(mime, Some(&"image/png".into()));
+        assert_eq!(
+            handle.get(&moved_entity, &"blob/name".into()).next(),
+            Some(&"moved.txt".into())
+        );
+
+        Ok(())
+    }
+}
@@ -1,8 +1,14 @@
 use db::*;
-use extractor::Extractor;
+use extractor::{Extractor, Failure, FailureReport, RetryPolicy};
 use handle::Handle;
-use std::{error::Error, sync::mpsc, time::Duration};
+use std::{
+    error::Error,
+    sync::mpsc::{self, RecvTimeoutError},
+    time::{Duration, Instant},
+};
 
+pub mod cli;
+pub mod config;
 pub mod db;
 pub mod extractor;
 pub mod graph_export;
@@ -12,8 +18,7 @@ pub mod handle;
 macro_rules! make_extractors {
     ($($extractor:expr),*) => {
         {
-            let mut extractors: Vec<Box<dyn $crate::extractor::Extractor>> = Vec::new();
-            $(extractors.push(Box::new($extractor));)*
+            let extractors: Vec<Box<dyn $crate::extractor::Extractor>> = vec![$(Box::new($extractor)),*];
             extractors
         }
     };
@@ -21,20 +26,182 @@ macro_rules! make_extractors {
 
 pub fn run_extractors(
     write_log: mpsc::Receiver<(Entity, Attribute, Value)>,
-    mut handle: &mut Handle,
+    handle: &mut Handle,
     extractors: &mut Vec<Box<dyn Extractor>>,
-) -> Result<(), Box<dyn Error>> {
-    // Give all extractors a chance to initialize
+) -> Result<FailureReport, Box<dyn Error>> {
+    run_extractors_with_policy(write_log, handle, extractors, &RetryPolicy::default())
+}
+
+/// Runs all extractors until the write log has been drained, retrying failed calls according to
+/// the given policy. Calls that still fail afterwards are recorded as failures in the database.
+pub fn run_extractors_with_policy(
+    write_log: mpsc::Receiver<(Entity, Attribute, Value)>,
+    handle: &mut Handle,
+    extractors: &mut Vec<Box<dyn Extractor>>,
+    policy: &RetryPolicy,
+) -> Result<FailureReport, Box<dyn Error>> {
+    init_extractors(handle, extractors);
+    process_write_log(&write_log, handle, extractors, policy)
+}
+
+/// Gives all extractors a chance to initialize, failures are recorded in the database
+pub fn init_extractors(handle: &mut Handle, extractors: &mut [Box<dyn Extractor>]) {
     for extractor in extractors.iter_mut() {
-        extractor.init(&mut handle)?;
+        if let Err(e) = extractor.init(handle) {
+            Failure::new(extractor.name(), None, None, e.as_ref(), 1).record(handle);
+        }
     }
+}
+
+/// How often live extractors are polled while processing the write log
+const POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Feeds everything in the write log to already initialized extractors until it is drained,
+/// e.g. to process blobs that have been imported after the initial run. Live extractors are
+/// polled in the meantime, so this only returns once none of them are live anymore.
+pub fn process_write_log(
+    write_log: &mpsc::Receiver<(Entity, Attribute, Value)>,
+    handle: &mut Handle,
+    extractors: &mut [Box<dyn Extractor>],
+    policy: &RetryPolicy,
+) -> Result<FailureReport, Box<dyn Error>> {
+    let mut polled = Instant::now();
 
     // Run over all the stuff
-    while let Ok((e, a, v)) = write_log.recv_timeout(Duration::from_millis(100)) {
-        for extractor in extractors.iter_mut() {
-            extractor.entry_added(&mut handle, &e, &a, &v).ok();
+    loop {
+        // Polling on a schedule rather than only when idle, a busy write log would starve
+        // live extractors otherwise
+        if polled.elapsed() >= POLL_INTERVAL && extractors.iter().any(|e| e.is_live()) {
+            poll_extractors(handle, extractors);
+            polled = Instant::now();
+        }
+
+        let (e, a, v) = match write_log.recv_timeout(POLL_INTERVAL) {
+            Ok(entry) => entry,
+            Err(RecvTimeoutError::Timeout) if extractors.iter().any(|e| e.is_live()) => continue,
+            Err(_) => break,
+        };
+
+        dispatch(handle, extractors, policy, &e, &a, &v);
+    }
+
+    Ok(FailureReport::load(handle))
+}
+
+/// Like [`process_write_log`], but returns as soon as the write log is empty instead of
+/// waiting on live extractors. They are polled once beforehand, so changes they picked up
+/// so far are processed as well. Meant for callers that have other things to do, like
+/// answering requests in between.
+pub fn drain_write_log(
+    write_log: &mpsc::Receiver<(Entity, Attribute, Value)>,
+    handle: &mut Handle,
+    extractors: &mut [Box<dyn Extractor>],
+    policy: &RetryPolicy,
+) -> Result<FailureReport, Box<dyn Error>> {
+    poll_extractors(handle, extractors);
+
+    // Extractors insert synchronously, so an empty log means everything has been processed
+    while let Ok((e, a, v)) = write_log.try_recv() {
+        dispatch(handle, extractors, policy, &e, &a, &v);
+    }
+
+    Ok(FailureReport::load(handle))
+}
+
+fn poll_extractors(handle: &mut Handle, extractors: &mut [Box<dyn Extractor>]) {
+    for extractor in extractors.iter_mut().filter(|e| e.is_live()) {
+        if let Err(e) = extractor.poll(handle) {
+            Failure::new(extractor.name(), None, None, e.as_ref(), 1).record(handle);
+        }
+    }
+}
+
+/// Passes a single entry of the write log to all extractors, retrying failed calls
+fn dispatch(
+    handle: &mut Handle,
+    extractors: &mut [Box<dyn Extractor>],
+    policy: &RetryPolicy,
+    e: &Entity,
+    a: &Attribute,
+    v: &Value,
+) {
+    for extractor in extractors.iter_mut() {
+        let mut attempt = 1;
+
+        while let Err(error) = extractor.entry_added(handle, e, a, v) {
+            let failure = Failure::new(
+                extractor.name(),
+                Some(e),
+                Some((a, v)),
+                error.as_ref(),
+                attempt,
+            );
+
+            if !policy.should_retry(failure.kind, attempt) {
+                failure.record(handle);
+                break;
+            }
+
+            std::thread::sleep(policy.backoff);
+            attempt += 1;
         }
     }
+}
+
+#[cfg(test)]
+mod does {
+    use super::*;
+    use std::{cell::Cell, rc::Rc};
 
-    Ok(())
+    /// Keeps the write log busy until the watcher has been polled
+    struct Busy(Rc<Cell<bool>>);
+
+    impl Extractor for Busy {
+        fn entry_added(
+            &mut self,
+            handle: &mut Handle,
+            entity: &Entity,
+            _: &Attribute,
+            _: &Value,
+        ) -> Result<(), Box<dyn Error>> {
+            if !self.0.get() {
+                std::thread::sleep(Duration::from_millis(1));
+                handle.insert(entity.clone(), "busy/tick", "tick");
+            }
+
+            Ok(())
+        }
+    }
+
+    struct Watcher(Rc<Cell<bool>>);
+
+    impl Extractor for Watcher {
+        fn is_live(&self) -> bool {
+            !self.0.get()
+        }
+
+        fn poll(&mut self, _: &mut Handle) -> Result<(), Box<dyn Error>> {
+            self.0.set(true);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn poll_live_extractors_while_busy() -> Result<(), Box<dyn Error>> {
+        let (db, write_log) = Database::new();
+        let mut handle = Handle::new(db, "storage".into());
+        let polled = Rc::new(Cell::new(false));
+        let mut extractors = make_extractors![Busy(polled.clone()), Watcher(polled.clone())];
+
+        handle.insert("a", "busy/tick", "tick");
+        process_write_log(
+            &write_log,
+            &mut handle,
+            &mut extractors,
+            &RetryPolicy::none(),
+        )?;
+
+        assert!(polled.get());
+        Ok(())
+    }
 }